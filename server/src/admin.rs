@@ -0,0 +1,253 @@
+use crate::{
+    auth,
+    config::{ConfigFile, SharedConfig},
+    storage::Account,
+    GameServer, GameServerState,
+};
+use serde::{Deserialize, Serialize};
+use shared::{ServerMessage, Uuid};
+use std::{net::IpAddr, sync::Arc};
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection, Reply};
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Serialize)]
+struct UserInfo {
+    id: Uuid,
+    name: String,
+    address: Option<IpAddr>,
+    account_id: Option<Uuid>,
+    match_id: Option<Uuid>,
+    rating: u32,
+}
+
+#[derive(Serialize)]
+struct MatchInfo {
+    id: Uuid,
+    players: [Uuid; 2],
+    kills: [usize; 2],
+}
+
+#[derive(Deserialize)]
+struct Announcement {
+    text: String,
+}
+
+/// Requires `Authorization: Bearer <admin_token>`, rejecting everything if no token is configured.
+fn authorized(config: SharedConfig) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let expected = config.borrow().admin_token.clone();
+            async move {
+                let given = header
+                    .as_deref()
+                    .and_then(|header| header.strip_prefix("Bearer "));
+                match (expected, given) {
+                    (Some(expected), Some(given)) if auth::hashes_match(given, &expected) => Ok(()),
+                    _ => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn found(found: bool) -> StatusCode {
+    if found {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn list_users(game_server: GameServer) -> impl Reply {
+    let state = game_server.read().await;
+    let users: Vec<UserInfo> = state
+        .users
+        .iter()
+        .map(|(id, user)| UserInfo {
+            id: *id,
+            name: user.name.clone(),
+            address: user.address,
+            account_id: user.account_id,
+            match_id: user.match_id,
+            rating: user.rating,
+        })
+        .collect();
+    warp::reply::json(&users)
+}
+
+async fn list_matches(game_server: GameServer) -> impl Reply {
+    let state = game_server.read().await;
+    let matches: Vec<MatchInfo> = state
+        .matches
+        .iter()
+        .map(|(id, game)| MatchInfo {
+            id: *id,
+            players: game.players,
+            kills: game.kills,
+        })
+        .collect();
+    warp::reply::json(&matches)
+}
+
+async fn kick(id: Uuid, game_server: GameServer) -> impl Reply {
+    found(game_server.read().await.kick(id, "Kicked by an admin"))
+}
+
+fn ban_account(state: &mut GameServerState, account: &Account) -> StatusCode {
+    if let Err(e) = state.storage.set_banned(account.id, true) {
+        log::error!("failed to ban '{}': {}", account.name, e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    log::info!("banned '{}'", account.name);
+    state.kick_account(account.id, "This account is banned");
+    StatusCode::NO_CONTENT
+}
+
+async fn ban_user(id: Uuid, game_server: GameServer) -> impl Reply {
+    let mut state = game_server.write().await;
+    match state.account_of(id) {
+        Some(account) => ban_account(&mut state, &account),
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn ban(account_id: Uuid, game_server: GameServer) -> impl Reply {
+    let mut state = game_server.write().await;
+    match state.storage.account(account_id) {
+        Some(account) => ban_account(&mut state, &account),
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn unban(account_id: Uuid, game_server: GameServer) -> impl Reply {
+    let mut state = game_server.write().await;
+    if !state.storage.is_banned(account_id) {
+        return StatusCode::NOT_FOUND;
+    }
+    if let Err(e) = state.storage.set_banned(account_id, false) {
+        log::error!("failed to unban {}: {}", account_id, e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn ban_address(address: IpAddr, game_server: GameServer) -> impl Reply {
+    let mut state = game_server.write().await;
+    if let Err(e) = state.storage.set_address_banned(address, true) {
+        log::error!("failed to ban {}: {}", address, e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    log::info!("banned {}", address);
+    state.kick_address(address, "Your address is banned from this server");
+    StatusCode::NO_CONTENT
+}
+
+async fn unban_address(address: IpAddr, game_server: GameServer) -> impl Reply {
+    let mut state = game_server.write().await;
+    if !state.storage.is_address_banned(address) {
+        return StatusCode::NOT_FOUND;
+    }
+    if let Err(e) = state.storage.set_address_banned(address, false) {
+        log::error!("failed to unban {}: {}", address, e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn end_match(match_id: Uuid, game_server: GameServer) -> impl Reply {
+    found(game_server.write().await.abort_match(match_id))
+}
+
+async fn announce(announcement: Announcement, game_server: GameServer) -> impl Reply {
+    game_server
+        .read()
+        .await
+        .broadcast(&ServerMessage::Announcement {
+            text: announcement.text,
+        });
+    StatusCode::NO_CONTENT
+}
+
+/// The moderation endpoints below `/admin`.
+pub fn routes(
+    game_server: GameServer,
+    config: SharedConfig,
+    config_file: Arc<ConfigFile>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let game_server = warp::any().map(move || game_server.clone());
+
+    let users = warp::path!("users")
+        .and(warp::get())
+        .and(game_server.clone())
+        .then(list_users);
+    let matches = warp::path!("matches")
+        .and(warp::get())
+        .and(game_server.clone())
+        .then(list_matches);
+    let kick = warp::path!("users" / Uuid / "kick")
+        .and(warp::post())
+        .and(game_server.clone())
+        .then(kick);
+    let ban_user = warp::path!("users" / Uuid / "ban")
+        .and(warp::post())
+        .and(game_server.clone())
+        .then(ban_user);
+    let ban = warp::path!("accounts" / Uuid / "ban")
+        .and(warp::post())
+        .and(game_server.clone())
+        .then(ban);
+    let unban = warp::path!("accounts" / Uuid / "ban")
+        .and(warp::delete())
+        .and(game_server.clone())
+        .then(unban);
+    let ban_address = warp::path!("addresses" / IpAddr / "ban")
+        .and(warp::post())
+        .and(game_server.clone())
+        .then(ban_address);
+    let unban_address = warp::path!("addresses" / IpAddr / "ban")
+        .and(warp::delete())
+        .and(game_server.clone())
+        .then(unban_address);
+    let end_match = warp::path!("matches" / Uuid / "end")
+        .and(warp::post())
+        .and(game_server.clone())
+        .then(end_match);
+    let announce = warp::path!("announce")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(game_server)
+        .then(announce);
+    let reload = warp::path!("reload")
+        .and(warp::post())
+        .map(move || match config_file.reload() {
+            Ok(()) => warp::reply::with_status(String::new(), StatusCode::NO_CONTENT),
+            Err(e) => warp::reply::with_status(e.to_string(), StatusCode::BAD_REQUEST),
+        });
+
+    warp::path("admin").and(authorized(config)).and(
+        users
+            .or(matches)
+            .or(kick)
+            .or(ban_user)
+            .or(ban)
+            .or(unban)
+            .or(ban_address)
+            .or(unban_address)
+            .or(end_match)
+            .or(announce)
+            .or(reload),
+    )
+}
+
+/// Turns failed admin authorization into a `401`, everything else is left to warp.
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(StatusCode::UNAUTHORIZED)
+    } else {
+        Err(rejection)
+    }
+}
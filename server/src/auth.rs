@@ -0,0 +1,95 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").ok();
+        hex
+    })
+}
+
+/// Session tokens are handed to the client once and only stored as a hash.
+pub fn new_token() -> String {
+    to_hex(&rand::random::<[u8; 32]>())
+}
+
+/// Argon2id with the recommended parameters of 19 MiB and 2 iterations, as a PHC string
+/// that also holds the salt and the parameters. Takes a while on purpose, so it shouldn't
+/// run while the game state is locked.
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+        .map_err(|e| anyhow::anyhow!("failed to encode salt: {}", e))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+/// As slow as [`hash_password`], hashes that can't be parsed never match.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+/// Tokens are random enough that a single round of sha256 is fine for them.
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token))
+}
+
+/// Compares two hashes without leaking how many leading characters matched.
+pub fn hashes_match(left: &str, right: &str) -> bool {
+    left.len() == right.len()
+        && left
+            .bytes()
+            .zip(right.bytes())
+            .fold(0, |acc, (l, r)| acc | (l ^ r))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_hashes_only_match_their_password() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+    }
+
+    #[test]
+    fn password_hashes_are_salted() {
+        assert_ne!(
+            hash_password("hunter2").unwrap(),
+            hash_password("hunter2").unwrap()
+        );
+    }
+
+    #[test]
+    fn unparseable_password_hashes_never_match() {
+        assert!(!verify_password("hunter2", ""));
+        assert!(!verify_password("hunter2", &hash_token("hunter2")));
+    }
+
+    #[test]
+    fn token_hashes_match_only_their_token() {
+        let token = new_token();
+        let hash = hash_token(&token);
+        assert!(hashes_match(&hash_token(&token), &hash));
+        assert!(!hashes_match(&hash_token(&new_token()), &hash));
+    }
+
+    #[test]
+    fn hashes_of_different_length_never_match() {
+        assert!(!hashes_match("abc", "abcd"));
+        assert!(hashes_match("", ""));
+    }
+}
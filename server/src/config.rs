@@ -0,0 +1,528 @@
+use crate::limits::Limits;
+use anyhow::{anyhow, bail, Context};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+use shared::{
+    Arena, Behaviour, Edges, EnemyType, MatchSettings, Movement, Pickups, BOLT_SPEED, DEFAULT_MAP,
+    INVULNERABLE_SECONDS, MIN_TILE_SIZE, PLAYER_HEALTH, PLAYER_MANA, TICKRATE,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{mpsc, watch};
+
+/// The current configuration, updated whenever the config file changes.
+pub type SharedConfig = watch::Receiver<Config>;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Only read on startup.
+    pub listen: String,
+    /// Only read on startup.
+    pub storage: PathBuf,
+    pub game: GameConfig,
+    pub timeouts: Timeouts,
+    /// `channel_capacity` is only read on startup and `max_message_size` only applies to new
+    /// connections, everything else also applies to open ones.
+    pub limits: Limits,
+    pub names: NameRules,
+    /// Bearer token for the `/admin` endpoints, which are disabled without one.
+    pub admin_token: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:3030".to_owned(),
+            storage: PathBuf::from("mage_battle.json"),
+            game: GameConfig::default(),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            names: NameRules::default(),
+            admin_token: None,
+        }
+    }
+}
+
+/// Applies to matches started after the change.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    pub tick_rate: u64,
+    pub movement: Movement,
+    pub player_health: u32,
+    pub player_mana: f32,
+    /// How long players can't be hurt again after taking damage.
+    pub invulnerable_seconds: f32,
+    /// In world units per second.
+    pub bolt_speed: f32,
+    /// What players can spend their kills on, in the order they are offered.
+    pub enemies: Vec<EnemyType>,
+    pub pickups: Pickups,
+    /// Every match is played on one of these, picked at random.
+    pub maps: Vec<String>,
+    pub edges: Edges,
+}
+
+impl GameConfig {
+    pub fn settings(&self, rng: &mut impl Rng) -> MatchSettings {
+        let map = self.maps.choose(rng).map_or(DEFAULT_MAP, String::as_str);
+        MatchSettings {
+            tick_rate: self.tick_rate,
+            movement: self.movement,
+            health: self.player_health,
+            mana: self.player_mana,
+            invulnerable_seconds: self.invulnerable_seconds,
+            bolt_speed: self.bolt_speed,
+            arena: Arena {
+                map: map.to_owned(),
+                edges: self.edges,
+            },
+            enemies: self.enemies.clone(),
+            pickups: self.pickups,
+        }
+    }
+
+    /// The speed of whatever moves fastest: players with a speed pickup, enemies or bolts.
+    fn fastest(&self) -> f32 {
+        let player = self.movement.max_speed * self.pickups.speed_multiplier.max(1.);
+        self.enemies
+            .iter()
+            .map(|enemy| enemy.speed)
+            .fold(player.max(self.bolt_speed), f32::max)
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            tick_rate: TICKRATE,
+            movement: Movement::default(),
+            player_health: PLAYER_HEALTH,
+            player_mana: PLAYER_MANA,
+            invulnerable_seconds: INVULNERABLE_SECONDS,
+            bolt_speed: BOLT_SPEED,
+            enemies: vec![
+                EnemyType {
+                    name: "Imp".to_owned(),
+                    cost: 1,
+                    speed: 30.,
+                    health: 1,
+                    damage: 1,
+                    sprite: "wizard".to_owned(),
+                    behaviour: Behaviour::Chase,
+                },
+                EnemyType {
+                    name: "Wisp".to_owned(),
+                    cost: 3,
+                    speed: 50.,
+                    health: 2,
+                    damage: 1,
+                    sprite: "wizard".to_owned(),
+                    behaviour: Behaviour::Wander,
+                },
+                EnemyType {
+                    name: "Ogre".to_owned(),
+                    cost: 10,
+                    speed: 20.,
+                    health: 8,
+                    damage: 3,
+                    sprite: "wizard".to_owned(),
+                    behaviour: Behaviour::Chase,
+                },
+            ],
+            pickups: Pickups::default(),
+            maps: vec![DEFAULT_MAP.to_owned()],
+            edges: Edges::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    pub matchmaking_interval_seconds: u64,
+    /// How long running matches may take to finish when shutting down.
+    pub shutdown_seconds: u64,
+    /// How long players have to agree on a rematch after a match.
+    pub rematch_seconds: u64,
+    /// How long a challenge waits for an answer.
+    pub challenge_seconds: u64,
+    /// How often changes to accounts and bans are written to storage,
+    /// matches are written as soon as they finish.
+    pub save_interval_seconds: u64,
+    /// How long a session token from logging in can be used to log in again.
+    pub session_hours: u64,
+}
+
+impl Timeouts {
+    pub fn matchmaking_interval(&self) -> Duration {
+        Duration::from_secs(self.matchmaking_interval_seconds)
+    }
+
+    pub fn shutdown(&self) -> Duration {
+        Duration::from_secs(self.shutdown_seconds)
+    }
+
+    pub fn rematch(&self) -> Duration {
+        Duration::from_secs(self.rematch_seconds)
+    }
+
+    pub fn challenge(&self) -> Duration {
+        Duration::from_secs(self.challenge_seconds)
+    }
+
+    pub fn save_interval(&self) -> Duration {
+        Duration::from_secs(self.save_interval_seconds)
+    }
+
+    pub fn session(&self) -> Duration {
+        Duration::from_secs(self.session_hours * 60 * 60)
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            matchmaking_interval_seconds: 1,
+            shutdown_seconds: 60,
+            rematch_seconds: 30,
+            challenge_seconds: 30,
+            save_interval_seconds: 5,
+            session_hours: 7 * 24,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct NameRules {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Names containing any of these, ignoring case, are rejected.
+    pub blocked: Vec<String>,
+}
+
+impl NameRules {
+    /// Returns the reason the name was rejected.
+    pub fn check(&self, name: &str) -> Result<(), String> {
+        let length = name.chars().count();
+        if length < self.min_length || length > self.max_length {
+            return Err(format!(
+                "Names must be between {} and {} characters long",
+                self.min_length, self.max_length
+            ));
+        }
+        if name != name.trim() {
+            return Err("Names must not start or end with a space".to_owned());
+        }
+        if !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == ' ' || c == '_' || c == '-')
+        {
+            return Err("Names may only contain letters, digits, spaces, '_' and '-'".to_owned());
+        }
+        let lowercase = name.to_lowercase();
+        if self
+            .blocked
+            .iter()
+            .any(|blocked| lowercase.contains(&blocked.to_lowercase()))
+        {
+            return Err("This name is not allowed".to_owned());
+        }
+        Ok(())
+    }
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            min_length: 3,
+            max_length: 16,
+            blocked: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Uses the defaults if the file doesn't exist. Only for startup, a reload has to keep
+    /// the current config instead.
+    pub fn load_or_default(path: &Path) -> anyhow::Result<Self> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            log::info!("{} not found, using the default config", path.display());
+            Ok(Self::default())
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.game.tick_rate == 0 {
+            bail!("game.tick_rate must be greater than 0");
+        }
+        let movement = &self.game.movement;
+        if movement.max_speed <= 0. || movement.acceleration <= 0. || movement.friction <= 0. {
+            bail!("game.movement.max_speed, acceleration and friction must be greater than 0");
+        }
+        if self.game.player_health == 0 {
+            bail!("game.player_health must be greater than 0");
+        }
+        if self.game.player_mana < 0. {
+            bail!("game.player_mana must not be negative");
+        }
+        if self.game.invulnerable_seconds < 0. {
+            bail!("game.invulnerable_seconds must not be negative");
+        }
+        if self.game.bolt_speed <= 0. {
+            bail!("game.bolt_speed must be greater than 0");
+        }
+        if self.game.enemies.is_empty() {
+            bail!("game.enemies must not be empty");
+        }
+        for enemy in &self.game.enemies {
+            if enemy.cost == 0 || enemy.health == 0 || enemy.speed <= 0. {
+                bail!(
+                    "the cost, health and speed of enemy '{}' must be greater than 0",
+                    enemy.name
+                );
+            }
+        }
+        let pickups = &self.game.pickups;
+        if !(0. ..=1.).contains(&pickups.drop_chance) {
+            bail!("game.pickups.drop_chance must be between 0 and 1");
+        }
+        if pickups.mana < 0. || pickups.buff_seconds < 0. {
+            bail!("game.pickups.mana and buff_seconds must not be negative");
+        }
+        if pickups.speed_multiplier <= 0. {
+            bail!("game.pickups.speed_multiplier must be greater than 0");
+        }
+        let fastest = self.game.fastest();
+        #[allow(clippy::cast_precision_loss)]
+        let step = fastest / self.game.tick_rate as f32;
+        if step >= MIN_TILE_SIZE {
+            bail!(
+                "nothing may move {} world units or more per tick, the fastest speed of {} \
+                 covers {} at game.tick_rate {}",
+                MIN_TILE_SIZE,
+                fastest,
+                step,
+                self.game.tick_rate
+            );
+        }
+        if self.game.maps.is_empty() {
+            bail!("game.maps must not be empty");
+        }
+        if self.timeouts.matchmaking_interval_seconds == 0 {
+            bail!("timeouts.matchmaking_interval_seconds must be greater than 0");
+        }
+        if self.timeouts.rematch_seconds == 0 {
+            bail!("timeouts.rematch_seconds must be greater than 0");
+        }
+        if self.timeouts.challenge_seconds == 0 {
+            bail!("timeouts.challenge_seconds must be greater than 0");
+        }
+        if self.timeouts.save_interval_seconds == 0 {
+            bail!("timeouts.save_interval_seconds must be greater than 0");
+        }
+        if self.timeouts.session_hours == 0 {
+            bail!("timeouts.session_hours must be greater than 0");
+        }
+        let limits = &self.limits;
+        if limits.max_message_size == 0 || limits.channel_capacity == 0 {
+            bail!("limits.max_message_size and channel_capacity must be greater than 0");
+        }
+        for (name, rate) in [
+            ("login", limits.login),
+            ("lobby", limits.lobby),
+            ("state", limits.state),
+            ("violations", limits.violations),
+        ] {
+            if rate.per_second <= 0. || rate.burst < 1. {
+                bail!(
+                    "limits.{} must have a per_second greater than 0 and a burst of at least 1",
+                    name
+                );
+            }
+        }
+        // clients send their state every tick and enemies or their death on top of that
+        if limits.state.per_second < self.game.tick_rate as f64 * 1.25 {
+            bail!("limits.state.per_second must be at least 1.25 times game.tick_rate");
+        }
+        if self.names.min_length > self.names.max_length {
+            bail!("names.min_length must not be greater than names.max_length");
+        }
+        if self.names.blocked.iter().any(String::is_empty) {
+            bail!("names.blocked must not contain empty entries");
+        }
+        Ok(())
+    }
+}
+
+/// The config file and the channel every change to it is published on.
+pub struct ConfigFile {
+    path: PathBuf,
+    tx: watch::Sender<Config>,
+}
+
+impl ConfigFile {
+    pub fn new(path: PathBuf, config: Config) -> (Arc<Self>, SharedConfig) {
+        let (tx, rx) = watch::channel(config);
+        (Arc::new(Self { path, tx }), rx)
+    }
+
+    /// Reads the file again and publishes it if it is valid, a missing file is an error.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let new = Config::load(&self.path)?;
+        let old = self.tx.borrow();
+        if new.listen != old.listen
+            || new.storage != old.storage
+            || new.limits.channel_capacity != old.limits.channel_capacity
+        {
+            log::warn!("listen, storage and limits.channel_capacity only change after a restart");
+        }
+        drop(old);
+        log::info!("reloaded {}", self.path.display());
+        self.tx.send_replace(new);
+        Ok(())
+    }
+
+    /// Reloads the config whenever the file changes.
+    /// The returned watcher has to be kept alive for as long as changes should be picked up.
+    pub fn watch(self: &Arc<Self>) -> anyhow::Result<RecommendedWatcher> {
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            event_tx.send(event).ok();
+        })?;
+        // Editors often replace the file instead of writing to it, so the whole directory is watched.
+        let directory = self
+            .path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        let file_name = self
+            .path
+            .file_name()
+            .ok_or_else(|| anyhow!("{} is not a file", self.path.display()))?
+            .to_owned();
+        let config_file = self.clone();
+        tokio::spawn(async move {
+            while let Some(event) = event_rx.recv().await {
+                let event: notify::Event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        log::warn!("config watcher error: {}", e);
+                        continue;
+                    }
+                };
+                if !event.kind.is_modify() && !event.kind.is_create() {
+                    continue;
+                }
+                if !event
+                    .paths
+                    .iter()
+                    .any(|changed| changed.file_name() == Some(&file_name))
+                {
+                    continue;
+                }
+                if let Err(e) = config_file.reload() {
+                    log::error!(
+                        "keeping the old config, {} is invalid: {}",
+                        config_file.path.display(),
+                        e
+                    );
+                }
+            }
+        });
+        Ok(watcher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::Uuid;
+
+    #[test]
+    fn names_must_fit_the_length_limits() {
+        let rules = NameRules::default();
+        assert!(rules.check("ab").is_err());
+        assert!(rules.check("abc").is_ok());
+        assert!(rules.check(&"a".repeat(16)).is_ok());
+        assert!(rules.check(&"a".repeat(17)).is_err());
+    }
+
+    #[test]
+    fn length_counts_characters_not_bytes() {
+        assert!(NameRules::default().check("äöü").is_ok());
+    }
+
+    #[test]
+    fn names_only_use_allowed_characters() {
+        let rules = NameRules::default();
+        assert!(rules.check("Mage_Lord-2 x").is_ok());
+        assert!(rules.check(" mage").is_err());
+        assert!(rules.check("mage!").is_err());
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn nothing_may_cross_a_tile_in_one_tick() {
+        let mut config = Config::default();
+        config.game.tick_rate = 4;
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.game.enemies[0].speed = MIN_TILE_SIZE * 64.;
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.game.pickups.speed_multiplier = 20.;
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.game.tick_rate = 10;
+        assert!(config.validate().is_err());
+        config.game.bolt_speed = 150.;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn reloading_a_missing_file_keeps_the_config() {
+        let path = std::env::temp_dir().join(format!("mage_battle_{}.toml", Uuid::new_v4()));
+        let config = Config {
+            admin_token: Some("secret".to_owned()),
+            ..Config::default()
+        };
+        let (config_file, current) = ConfigFile::new(path.clone(), config);
+        assert!(config_file.reload().is_err());
+        assert_eq!(current.borrow().admin_token.as_deref(), Some("secret"));
+        assert!(Config::load_or_default(&path).is_ok());
+    }
+
+    #[test]
+    fn blocked_words_are_matched_ignoring_case() {
+        let rules = NameRules {
+            blocked: vec!["admin".to_owned()],
+            ..NameRules::default()
+        };
+        assert!(rules.check("TheAdmin").is_err());
+        assert!(rules.check("mage").is_ok());
+    }
+}
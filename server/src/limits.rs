@@ -0,0 +1,172 @@
+use serde::Deserialize;
+use shared::{ClientMessage, TICKRATE};
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Rate {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+impl Rate {
+    pub const fn new(per_second: f64, burst: f64) -> Self {
+        Self { per_second, burst }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Largest websocket message a client may send, in bytes.
+    pub max_message_size: usize,
+    /// How many parsed messages may wait for the update loop before connections stop being read.
+    pub channel_capacity: usize,
+    pub login: Rate,
+    pub lobby: Rate,
+    pub state: Rate,
+    /// How many rate limited messages are tolerated before the client gets disconnected.
+    pub violations: Rate,
+    /// How many undecodable messages are tolerated before the client gets disconnected.
+    pub max_strikes: u32,
+}
+
+impl Default for Limits {
+    #[allow(clippy::cast_precision_loss)]
+    fn default() -> Self {
+        Self {
+            max_message_size: 4 * 1024,
+            channel_capacity: 1024,
+            login: Rate::new(0.5, 5.),
+            lobby: Rate::new(5., 20.),
+            state: Rate::new(TICKRATE as f64 * 1.5, TICKRATE as f64),
+            violations: Rate::new(1., 50.),
+            max_strikes: 10,
+        }
+    }
+}
+
+struct TokenBucket {
+    rate: Rate,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: Rate, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate.burst,
+            last_refill: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate.per_second).min(self.rate.burst);
+        self.last_refill = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub enum Verdict {
+    Allow,
+    Drop,
+    Disconnect,
+}
+
+/// Token buckets for a single connection, one per kind of message.
+pub struct RateLimiter {
+    login: TokenBucket,
+    lobby: TokenBucket,
+    state: TokenBucket,
+    violations: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &Limits, now: Instant) -> Self {
+        Self {
+            login: TokenBucket::new(limits.login, now),
+            lobby: TokenBucket::new(limits.lobby, now),
+            state: TokenBucket::new(limits.state, now),
+            violations: TokenBucket::new(limits.violations, now),
+        }
+    }
+
+    /// Applies changed limits, keeping the tokens that are left.
+    pub fn update(&mut self, limits: &Limits) {
+        self.login.rate = limits.login;
+        self.lobby.rate = limits.lobby;
+        self.state.rate = limits.state;
+        self.violations.rate = limits.violations;
+    }
+
+    pub fn check(&mut self, msg: &ClientMessage, now: Instant) -> Verdict {
+        let bucket = match msg {
+            ClientMessage::Login { .. } | ClientMessage::Resume { .. } => &mut self.login,
+            ClientMessage::State { .. } | ClientMessage::SendEnemy { .. } | ClientMessage::Died => {
+                &mut self.state
+            }
+            _ => &mut self.lobby,
+        };
+        if bucket.try_take(now) {
+            Verdict::Allow
+        } else if self.violations.try_take(now) {
+            Verdict::Drop
+        } else {
+            Verdict::Disconnect
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Rate::new(2., 3.), start);
+        assert!((0..3).all(|_| bucket.try_take(start)));
+        assert!(!bucket.try_take(start));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn bucket_never_holds_more_than_the_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Rate::new(10., 2.), start);
+        let later = start + Duration::from_secs(60);
+        assert!((0..2).all(|_| bucket.try_take(later)));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn limiter_drops_then_disconnects() {
+        let now = Instant::now();
+        let limits = Limits {
+            lobby: Rate::new(1., 1.),
+            violations: Rate::new(1., 1.),
+            ..Limits::default()
+        };
+        let mut limiter = RateLimiter::new(&limits, now);
+        assert!(matches!(
+            limiter.check(&ClientMessage::JoinQueue, now),
+            Verdict::Allow
+        ));
+        assert!(matches!(
+            limiter.check(&ClientMessage::JoinQueue, now),
+            Verdict::Drop
+        ));
+        assert!(matches!(
+            limiter.check(&ClientMessage::JoinQueue, now),
+            Verdict::Disconnect
+        ));
+    }
+}
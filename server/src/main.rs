@@ -1,26 +1,705 @@
 #![warn(clippy::pedantic, clippy::perf)]
 
+mod admin;
+mod auth;
+mod config;
+mod limits;
+mod matchmaking;
+mod metrics;
+mod storage;
+
 use clap::Parser;
-use shared::{deserialize, serialize, ClientMessage, ServerMessage, Uuid};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tokio::sync::{mpsc, RwLock};
+use config::{Config, ConfigFile, SharedConfig};
+use glam::Vec2;
+use limits::{RateLimiter, Verdict};
+use matchmaking::{Matchmaker, INITIAL_RATING};
+use metrics::{Metrics, Status};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use shared::{
+    deserialize, serialize, ClientMessage, ErrorCode, MatchSettings, ServerMessage, Uuid,
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use storage::{Account, FileStorage, MatchRecord, SaveQueue, Saver, Storage};
+use tokio::sync::{mpsc, Notify, RwLock};
 use warp::{
     ws::{Message, WebSocket},
     Filter,
 };
 
-#[derive(Default)]
+/// How far clients may be ahead of the server's clock.
+const CLOCK_LEEWAY: Duration = Duration::from_millis(500);
+
 struct GameServerState {
     users: HashMap<Uuid, User>,
+    matches: HashMap<Uuid, Match>,
+    challenges: HashMap<Uuid, Challenge>,
+    /// By the id of the finished match.
+    rematches: HashMap<Uuid, Rematch>,
+    matchmaker: Matchmaker,
+    storage: Box<dyn Storage>,
+    saves: SaveQueue,
+    config: SharedConfig,
+    /// Picks match seeds and maps, seeded from `--seed` to make them reproducible.
+    rng: StdRng,
+    shutting_down: bool,
 }
 
 struct User {
     tx: OutBoundChannel,
-    in_game: bool,
+    /// Makes the connection stop reading even if the client ignores the close frame.
+    kicked: Arc<Notify>,
+    address: Option<IpAddr>,
+    account_id: Option<Uuid>,
+    match_id: Option<Uuid>,
     name: String,
+    rating: u32,
+}
+
+struct Match {
+    players: [Uuid; 2],
+    kills: [usize; 2],
+    /// Kills spent on sending enemies.
+    spent: [usize; 2],
+    /// How many enemies each player sent to the other.
+    sent: [usize; 2],
+    /// Ticks and how far the players moved, as of their last accepted state.
+    ticks: [u32; 2],
+    moved: [Vec2; 2],
+    started: Instant,
+    settings: MatchSettings,
+}
+
+impl Match {
+    fn index_of(&self, id: Uuid) -> Option<usize> {
+        self.players.iter().position(|player| *player == id)
+    }
+}
+
+/// Two players that just finished a match, a new one starts once both want it.
+struct Rematch {
+    players: [Uuid; 2],
+    wanted: [bool; 2],
+    offered_at: Instant,
 }
+
+struct Challenge {
+    challenger: Uuid,
+    challenged: Uuid,
+    sent: Instant,
+}
+
 type GameServer = Arc<RwLock<GameServerState>>;
 
+impl GameServerState {
+    fn new(storage: Box<dyn Storage>, saves: SaveQueue, config: SharedConfig, rng: StdRng) -> Self {
+        Self {
+            users: HashMap::new(),
+            matches: HashMap::new(),
+            challenges: HashMap::new(),
+            rematches: HashMap::new(),
+            matchmaker: Matchmaker::default(),
+            storage,
+            saves,
+            config,
+            rng,
+            shutting_down: false,
+        }
+    }
+
+    /// Hands the changes to the storage over to the [`Saver`], which writes them without
+    /// holding the lock.
+    fn save(&mut self) {
+        match self.storage.snapshot() {
+            Ok(Some(snapshot)) => self.saves.push(snapshot),
+            Ok(None) => {}
+            Err(e) => log::error!("failed to take a snapshot of the storage: {}", e),
+        }
+    }
+
+    fn send_to(&self, id: Uuid, msg: &ServerMessage) {
+        if let Some(user) = self.users.get(&id) {
+            send_msg(&user.tx, msg);
+        }
+    }
+
+    fn broadcast(&self, msg: &ServerMessage) {
+        for user in self.users.values() {
+            send_msg(&user.tx, msg);
+        }
+    }
+
+    fn account_of(&self, id: Uuid) -> Option<Account> {
+        self.users
+            .get(&id)
+            .and_then(|user| user.account_id)
+            .and_then(|account_id| self.storage.account(account_id))
+    }
+
+    fn is_authenticated(&self, id: Uuid) -> bool {
+        self.users
+            .get(&id)
+            .is_some_and(|user| user.account_id.is_some())
+    }
+
+    fn login_failed(&self, id: Uuid, reason: &str) {
+        self.send_to(
+            id,
+            &ServerMessage::LoginFailed {
+                reason: reason.to_owned(),
+            },
+        );
+    }
+
+    /// The account to log into, `None` if there is none with the name and it can be registered.
+    fn find_login(&self, name: &str, password: &str) -> Result<Option<Account>, String> {
+        if name.trim().is_empty() || password.is_empty() {
+            return Err("Name and password must not be empty".to_owned());
+        }
+        if let Some(account) = self.storage.account_by_name(name) {
+            return Ok(Some(account));
+        }
+        self.config.borrow().names.check(name)?;
+        Ok(None)
+    }
+
+    fn resume(&mut self, id: Uuid, name: &str, token: &str) {
+        match self.storage.account_by_name(name) {
+            Some(account) if account.verify_token(token) => self.enter_lobby(id, account),
+            _ => self.login_failed(id, "Session expired, please log in again"),
+        }
+    }
+
+    /// Renames the account of the user if the name follows the rules and isn't taken.
+    fn change_name(&mut self, id: Uuid, name: String) {
+        if let Err(reason) = self.config.borrow().names.check(&name) {
+            self.send_to(id, &ServerMessage::NameRejected { name, reason });
+            return;
+        }
+        let Some(mut account) = self.account_of(id) else {
+            return;
+        };
+        if self
+            .storage
+            .account_by_name(&name)
+            .is_some_and(|other| other.id != account.id)
+        {
+            self.send_to(id, &ServerMessage::NameNotAvailable { name });
+            return;
+        }
+        account.name.clone_from(&name);
+        if let Err(e) = self.storage.save_account(&account) {
+            log::error!("failed to rename account {}: {}", account.id, e);
+            return;
+        }
+        if let Some(user) = self.users.get_mut(&id) {
+            user.name.clone_from(&name);
+        }
+        self.broadcast(&ServerMessage::PlayerChangedName { id, new_name: name });
+    }
+
+    /// Binds the connection to the account and issues a fresh session token.
+    fn enter_lobby(&mut self, id: Uuid, mut account: Account) {
+        if self.storage.is_banned(account.id) {
+            self.login_failed(id, "This account is banned");
+            return;
+        }
+        if self
+            .users
+            .values()
+            .any(|user| user.account_id == Some(account.id))
+        {
+            self.login_failed(id, "This account is already logged in");
+            return;
+        }
+        if self
+            .storage
+            .account_by_name(&account.name)
+            .is_some_and(|other| other.id != account.id)
+        {
+            // someone else registered the name while the password was hashed
+            self.login_failed(id, "Wrong name or password");
+            return;
+        }
+        let token = account.issue_token(self.config.borrow().timeouts.session());
+        if let Err(e) = self.storage.save_account(&account) {
+            log::error!("failed to save account '{}': {}", account.name, e);
+            self.login_failed(id, "Internal server error");
+            return;
+        }
+        self.send_to(id, &ServerMessage::LoggedIn { token });
+        log::debug!(
+            "{} logged in as '{}' ({} matches played)",
+            id,
+            account.name,
+            self.storage.match_count(account.id)
+        );
+        let Some(user) = self.users.get_mut(&id) else {
+            return;
+        };
+        user.account_id = Some(account.id);
+        user.name.clone_from(&account.name);
+        user.rating = account.rating;
+        for (other_id, other) in &self.users {
+            if *other_id != id && other.account_id.is_some() {
+                self.send_to(
+                    id,
+                    &ServerMessage::PlayerJoined {
+                        id: *other_id,
+                        name: other.name.clone(),
+                        rating: other.rating,
+                    },
+                );
+            }
+        }
+        self.broadcast(&ServerMessage::PlayerJoined {
+            id,
+            name: account.name,
+            rating: account.rating,
+        });
+    }
+
+    fn is_available(&self, id: Uuid) -> bool {
+        self.users
+            .get(&id)
+            .is_some_and(|user| user.match_id.is_none())
+    }
+
+    /// Returns `false` if one of the players can't play right now.
+    fn start_match(&mut self, players: [Uuid; 2]) -> bool {
+        if self.shutting_down || !players.iter().all(|id| self.is_available(*id)) {
+            return false;
+        }
+        for id in players {
+            self.leave_results(id);
+        }
+        self.cancel_challenges(|challenge| {
+            players.contains(&challenge.challenger) || players.contains(&challenge.challenged)
+        });
+        let match_id = Uuid::new_v4();
+        let seed: u64 = self.rng.gen();
+        let settings = self.config.borrow().game.settings(&mut self.rng);
+        for (index, id) in players.iter().enumerate() {
+            self.matchmaker.remove(*id);
+            if let Some(user) = self.users.get_mut(id) {
+                user.match_id = Some(match_id);
+            }
+            self.send_to(
+                *id,
+                &ServerMessage::MatchStarted {
+                    match_id,
+                    opponent: players[1 - index],
+                    seed,
+                    settings: settings.clone(),
+                },
+            );
+        }
+        log::debug!("match {} started: {:?}", match_id, players);
+        self.matches.insert(
+            match_id,
+            Match {
+                players,
+                kills: [0, 0],
+                spent: [0, 0],
+                sent: [0, 0],
+                ticks: [0, 0],
+                moved: [Vec2::ZERO; 2],
+                started: Instant::now(),
+                settings,
+            },
+        );
+        true
+    }
+
+    /// Passes the challenge on to the player with the name, or denies it right away if they
+    /// can't play against the challenger now.
+    fn challenge(&mut self, id: Uuid, name: &str) {
+        let request_id = Uuid::new_v4();
+        let challenged = self
+            .users
+            .iter()
+            .find(|(other_id, user)| {
+                **other_id != id
+                    && user.account_id.is_some()
+                    && user.name.to_lowercase() == name.to_lowercase()
+            })
+            .map(|(other_id, _)| *other_id)
+            .filter(|other_id| {
+                !self.shutting_down && self.is_available(id) && self.is_available(*other_id)
+            });
+        let (Some(challenged), Some(challenger_name)) = (
+            challenged,
+            self.users.get(&id).map(|user| user.name.clone()),
+        ) else {
+            self.send_to(id, &ServerMessage::ChallengeDenied { request_id });
+            return;
+        };
+        self.challenges.insert(
+            request_id,
+            Challenge {
+                challenger: id,
+                challenged,
+                sent: Instant::now(),
+            },
+        );
+        self.send_to(
+            challenged,
+            &ServerMessage::ChallengeReceived {
+                request_id,
+                name: challenger_name,
+            },
+        );
+        self.send_to(id, &ServerMessage::RequestReceived { request_id });
+    }
+
+    fn answer_challenge(&mut self, id: Uuid, request_id: Uuid, accept: bool) {
+        if self
+            .challenges
+            .get(&request_id)
+            .is_none_or(|challenge| challenge.challenged != id)
+        {
+            return;
+        }
+        let Some(challenge) = self.challenges.remove(&request_id) else {
+            return;
+        };
+        if accept && self.start_match([challenge.challenger, challenge.challenged]) {
+            return;
+        }
+        self.send_to(
+            challenge.challenger,
+            &ServerMessage::ChallengeDenied { request_id },
+        );
+    }
+
+    /// Drops the challenges, telling both sides they are off.
+    fn cancel_challenges(&mut self, cancel: impl Fn(&Challenge) -> bool) {
+        let mut cancelled = Vec::new();
+        self.challenges.retain(|request_id, challenge| {
+            if cancel(challenge) {
+                cancelled.push((*request_id, [challenge.challenger, challenge.challenged]));
+                false
+            } else {
+                true
+            }
+        });
+        for (request_id, players) in cancelled {
+            for id in players {
+                self.send_to(id, &ServerMessage::ChallengeDenied { request_id });
+            }
+        }
+    }
+
+    /// Drops challenges that weren't answered in time.
+    fn expire_challenges(&mut self) {
+        let timeout = self.config.borrow().timeouts.challenge();
+        self.cancel_challenges(|challenge| challenge.sent.elapsed() >= timeout);
+    }
+
+    /// The match the user is playing and their index in it.
+    fn match_of(&mut self, id: Uuid) -> Option<(&mut Match, usize)> {
+        let game = self
+            .users
+            .get(&id)
+            .and_then(|user| user.match_id)
+            .and_then(|match_id| self.matches.get_mut(&match_id))?;
+        let index = game.index_of(id)?;
+        Some((game, index))
+    }
+
+    /// Ignores states from another match, like a previous one against the same opponent,
+    /// and from players moving faster than the match allows.
+    /// Takes the kills from the state if its movement could have happened in the ticks since
+    /// the last one, and those ticks fit into the time since the match started.
+    fn update_state(&mut self, id: Uuid, match_id: Uuid, kills: usize, ticks: u32, moved: Vec2) {
+        if self.users.get(&id).and_then(|user| user.match_id) != Some(match_id) {
+            return;
+        }
+        let Some((game, index)) = self.match_of(id) else {
+            return;
+        };
+        let settings = &game.settings;
+        let Some(passed) = ticks.checked_sub(game.ticks[index]) else {
+            log::warn!("{id} went back from tick {} to {ticks}", game.ticks[index]);
+            return;
+        };
+        if Duration::from_secs_f32(settings.tick()) * ticks > game.started.elapsed() + CLOCK_LEEWAY
+        {
+            log::warn!("{id} is ahead of the server at tick {ticks}");
+            return;
+        }
+        let step = moved - game.moved[index];
+        if !settings.movement.allows(
+            step,
+            passed,
+            settings.tick(),
+            settings.pickups.speed_multiplier,
+        ) {
+            log::warn!(
+                "{id} moves too fast: {} units in {passed} ticks",
+                step.length()
+            );
+            return;
+        }
+        game.ticks[index] = ticks;
+        game.moved[index] = moved;
+        game.kills[index] = game.kills[index].max(kills);
+    }
+
+    /// Sends the enemy to the opponent if the user has enough unspent kills.
+    fn send_enemy(&mut self, id: Uuid, enemy: usize) {
+        let Some((game, index)) = self.match_of(id) else {
+            return;
+        };
+        let Some(cost) = game.settings.enemies.get(enemy).map(|enemy| enemy.cost) else {
+            return;
+        };
+        if game.kills[index] - game.spent[index] < cost {
+            return;
+        }
+        game.spent[index] += cost;
+        game.sent[index] += 1;
+        let opponent = game.players[1 - index];
+        self.send_to(
+            opponent,
+            &ServerMessage::Update {
+                spawns: vec![enemy],
+            },
+        );
+    }
+
+    /// Ends the match, applies the rating change and tells both players how it went.
+    /// They can ask for a rematch until one of them leaves the results, unless the server
+    /// is shutting down.
+    fn finish_match(&mut self, match_id: Uuid, winner: Uuid) {
+        let Some(game) = self.matches.remove(&match_id) else {
+            return;
+        };
+        let rating_changes = self
+            .update_ratings(match_id, &game, winner)
+            .unwrap_or_default();
+        self.save();
+        self.send_results(&game, Some(winner), rating_changes);
+        if !self.shutting_down {
+            self.rematches.insert(
+                match_id,
+                Rematch {
+                    players: game.players,
+                    wanted: [false, false],
+                    offered_at: Instant::now(),
+                },
+            );
+        }
+        log::debug!("match {} finished, winner: {}", match_id, winner);
+    }
+
+    /// Records the match and returns how much the rating of each player changed,
+    /// `None` if one of them has no account anymore.
+    fn update_ratings(&mut self, match_id: Uuid, game: &Match, winner: Uuid) -> Option<[i64; 2]> {
+        let winner_index = game.index_of(winner)?;
+        let loser = game.players[1 - winner_index];
+        let mut winner_account = self.account_of(winner)?;
+        let mut loser_account = self.account_of(loser)?;
+        let old_ratings = (winner_account.rating, loser_account.rating);
+        (winner_account.rating, loser_account.rating) =
+            matchmaking::update_ratings(winner_account.rating, loser_account.rating);
+        winner_account.wins += 1;
+        loser_account.losses += 1;
+        let record = MatchRecord::new(
+            match_id,
+            (winner_account.id, game.kills[winner_index]),
+            (loser_account.id, game.kills[1 - winner_index]),
+        );
+        for (id, account) in [(winner, &winner_account), (loser, &loser_account)] {
+            if let Err(e) = self.storage.save_account(account) {
+                log::error!("failed to save account '{}': {}", account.name, e);
+            }
+            if let Some(user) = self.users.get_mut(&id) {
+                user.rating = account.rating;
+            }
+            self.broadcast(&ServerMessage::PlayerRatingChanged {
+                id,
+                rating: account.rating,
+            });
+        }
+        if let Err(e) = self.storage.record_match(record) {
+            log::error!("failed to record match {}: {}", match_id, e);
+        }
+        let mut changes = [0; 2];
+        changes[winner_index] = i64::from(winner_account.rating) - i64::from(old_ratings.0);
+        changes[1 - winner_index] = i64::from(loser_account.rating) - i64::from(old_ratings.1);
+        Some(changes)
+    }
+
+    /// Takes both players out of the match and sends each of them a [`ServerMessage::Finish`].
+    fn send_results(&mut self, game: &Match, winner: Option<Uuid>, rating_changes: [i64; 2]) {
+        let seconds = game.started.elapsed().as_secs();
+        // aborted matches can't be played again, and no new match starts while shutting down
+        let rematch_seconds = if winner.is_some() && !self.shutting_down {
+            self.config.borrow().timeouts.rematch_seconds
+        } else {
+            0
+        };
+        for (index, id) in game.players.iter().enumerate() {
+            if let Some(user) = self.users.get_mut(id) {
+                user.match_id = None;
+            }
+            self.send_to(
+                *id,
+                &ServerMessage::Finish {
+                    enemy_kills: game.kills[1 - index],
+                    winner,
+                    enemies_sent: game.sent[index],
+                    enemies_received: game.sent[1 - index],
+                    seconds,
+                    rating_change: rating_changes[index],
+                    rematch_seconds,
+                },
+            );
+        }
+    }
+
+    /// Ends the match without a winner or rating changes.
+    fn abort_match(&mut self, match_id: Uuid) -> bool {
+        let Some(game) = self.matches.remove(&match_id) else {
+            return false;
+        };
+        log::warn!("aborting match {}", match_id);
+        self.send_results(&game, None, [0, 0]);
+        true
+    }
+
+    fn abort_matches(&mut self) {
+        let match_ids: Vec<Uuid> = self.matches.keys().copied().collect();
+        for match_id in match_ids {
+            self.abort_match(match_id);
+        }
+    }
+
+    fn kick(&self, id: Uuid, reason: &str) -> bool {
+        let Some(user) = self.users.get(&id) else {
+            return false;
+        };
+        log::info!("kicking {}: {}", id, reason);
+        disconnect(&user.tx, reason);
+        user.kicked.notify_one();
+        true
+    }
+
+    /// Kicks every connection logged into the account.
+    fn kick_account(&self, account_id: Uuid, reason: &str) {
+        for (id, user) in &self.users {
+            if user.account_id == Some(account_id) {
+                self.kick(*id, reason);
+            }
+        }
+    }
+
+    /// Kicks every connection from the address.
+    fn kick_address(&self, address: IpAddr, reason: &str) {
+        for (id, user) in &self.users {
+            if user.address == Some(address) {
+                self.kick(*id, reason);
+            }
+        }
+    }
+
+    /// Ends the match the user is playing, their opponent wins.
+    fn forfeit(&mut self, id: Uuid) {
+        let Some(match_id) = self.users.get(&id).and_then(|user| user.match_id) else {
+            return;
+        };
+        let opponent = self
+            .matches
+            .get(&match_id)
+            .and_then(|game| game.players.iter().copied().find(|player| *player != id));
+        if let Some(opponent) = opponent {
+            self.finish_match(match_id, opponent);
+        }
+    }
+
+    /// Offers the last opponent a rematch, or accepts theirs, which starts a new match.
+    fn rematch(&mut self, id: Uuid) {
+        let Some((&match_id, rematch)) = self
+            .rematches
+            .iter_mut()
+            .find(|(_, rematch)| rematch.players.contains(&id))
+        else {
+            return;
+        };
+        let Some(index) = rematch.players.iter().position(|player| *player == id) else {
+            return;
+        };
+        if rematch.wanted[index] {
+            return;
+        }
+        rematch.wanted[index] = true;
+        let players = rematch.players;
+        if rematch.wanted == [true, true] {
+            self.rematches.remove(&match_id);
+            if !self.start_match(players) {
+                for id in players {
+                    self.send_to(id, &ServerMessage::RematchCancelled { expired: true });
+                }
+            }
+        } else {
+            self.send_to(players[1 - index], &ServerMessage::RematchOffered);
+        }
+    }
+
+    /// Turns down a rematch with the last opponent, who is told about it.
+    fn leave_results(&mut self, id: Uuid) {
+        let mut opponents = Vec::new();
+        self.rematches.retain(|_, rematch| {
+            if !rematch.players.contains(&id) {
+                return true;
+            }
+            opponents.extend(rematch.players.iter().filter(|player| **player != id));
+            false
+        });
+        for opponent in opponents {
+            self.send_to(
+                opponent,
+                &ServerMessage::RematchCancelled { expired: false },
+            );
+        }
+    }
+
+    /// Sends both players back to the lobby if they didn't agree on a rematch in time,
+    /// or right away with `all`.
+    fn expire_rematches(&mut self, all: bool) {
+        let timeout = self.config.borrow().timeouts.rematch();
+        let mut expired = Vec::new();
+        self.rematches.retain(|_, rematch| {
+            if all || rematch.offered_at.elapsed() >= timeout {
+                expired.extend(rematch.players);
+                false
+            } else {
+                true
+            }
+        });
+        for id in expired {
+            self.send_to(id, &ServerMessage::RematchCancelled { expired: true });
+        }
+    }
+
+    /// Removes every trace of the user, handing the win to their opponent if they were in a match.
+    fn remove_user(&mut self, id: Uuid) {
+        self.matchmaker.remove(id);
+        self.cancel_challenges(|challenge| {
+            challenge.challenger == id || challenge.challenged == id
+        });
+        self.forfeit(id);
+        self.leave_results(id);
+        self.users.remove(&id);
+    }
+}
+
 fn send_welcome(out: &OutBoundChannel, seed: u64) -> Uuid {
     let id = Uuid::new_v4();
     let states = ServerMessage::Welcome { id };
@@ -31,7 +710,22 @@ fn send_welcome(out: &OutBoundChannel, seed: u64) -> Uuid {
 fn send_msg(tx: &OutBoundChannel, msg: &ServerMessage) {
     let buffer = serialize(msg).unwrap();
     let msg = Message::binary(buffer);
-    tx.send(Ok(msg)).unwrap();
+    if tx.send(Ok(msg)).is_err() {
+        log::debug!("dropping message to closed connection");
+    }
+}
+
+/// Tells the client why it gets disconnected and closes the websocket.
+fn disconnect(tx: &OutBoundChannel, reason: &str) {
+    send_msg(
+        tx,
+        &ServerMessage::Disconnected {
+            reason: reason.to_owned(),
+        },
+    );
+    if tx.send(Ok(Message::close())).is_err() {
+        log::debug!("connection already closed");
+    }
 }
 
 struct ClientMessageWrapper {
@@ -40,17 +734,20 @@ struct ClientMessageWrapper {
 }
 
 type OutBoundChannel = mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>;
-type ClientChannelSender = mpsc::UnboundedSender<ClientMessageWrapper>;
-type ClientChannelReceiver = mpsc::UnboundedReceiver<ClientMessageWrapper>;
+type ClientChannelSender = mpsc::Sender<ClientMessageWrapper>;
+type ClientChannelReceiver = mpsc::Receiver<ClientMessageWrapper>;
 
 fn create_send_channel(
     ws_sender: futures_util::stream::SplitSink<WebSocket, Message>,
+    metrics: Arc<Metrics>,
 ) -> OutBoundChannel {
     use futures_util::FutureExt;
     use futures_util::StreamExt;
     use tokio_stream::wrappers::UnboundedReceiverStream;
     let (sender, receiver) = mpsc::unbounded_channel();
-    let rx = UnboundedReceiverStream::new(receiver);
+    let rx = UnboundedReceiverStream::new(receiver).inspect(move |_| {
+        metrics.messages_sent.fetch_add(1, Ordering::Relaxed);
+    });
     tokio::task::spawn(rx.forward(ws_sender).map(|result| {
         if let Err(e) = result {
             log::error!("websocket send error: {}", e);
@@ -64,23 +761,48 @@ async fn user_connected(
     sender: ClientChannelSender,
     game_server: GameServer,
     seed: u64,
+    address: Option<IpAddr>,
+    mut config: SharedConfig,
+    metrics: Arc<Metrics>,
 ) {
     use futures_util::StreamExt;
     let (ws_sender, mut ws_receiver) = ws.split();
-    let tx = create_send_channel(ws_sender);
+    let tx = create_send_channel(ws_sender, metrics.clone());
+    if let Some(address) = address {
+        if game_server.read().await.storage.is_address_banned(address) {
+            log::info!("refusing connection from banned address {}", address);
+            disconnect(&tx, "Your address is banned from this server");
+            return;
+        }
+    }
     let my_id = send_welcome(&tx, seed);
+    let mut limits = config.borrow_and_update().limits.clone();
+    let mut rate_limiter = RateLimiter::new(&limits, Instant::now());
+    let mut strikes = 0;
+    let kicked = Arc::new(Notify::new());
     log::debug!("new user connected: {}", my_id);
     {
         game_server.write().await.users.insert(
             my_id,
             User {
-                tx,
+                tx: tx.clone(),
+                kicked: kicked.clone(),
+                address,
                 name: String::new(),
-                in_game: false,
+                account_id: None,
+                match_id: None,
+                rating: INITIAL_RATING,
             },
         );
     }
-    while let Some(result) = ws_receiver.next().await {
+    loop {
+        let result = tokio::select! {
+            result = ws_receiver.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            () = kicked.notified() => break,
+        };
         let msg = match result {
             Ok(msg) => msg,
             Err(e) => {
@@ -90,77 +812,194 @@ async fn user_connected(
         };
         log::debug!("user sent message: {:?}", msg);
 
-        if let Some(msg) = parse_message(msg) {
-            if sender
-                .send(ClientMessageWrapper { id: my_id, msg })
-                .is_err()
-            {
+        metrics.messages_received.fetch_add(1, Ordering::Relaxed);
+        let msg = match parse_message(msg) {
+            Ok(Some(msg)) => msg,
+            Ok(None) => continue,
+            Err(code) => {
+                metrics.malformed_messages.fetch_add(1, Ordering::Relaxed);
+                strikes += 1;
+                log::warn!(
+                    "undecodable message (id={}, code={:?}, strike {}/{})",
+                    my_id,
+                    code,
+                    strikes,
+                    limits.max_strikes
+                );
+                if strikes >= limits.max_strikes {
+                    disconnect(&tx, "Too many malformed messages");
+                    break;
+                }
+                send_msg(&tx, &ServerMessage::Error { code });
+                continue;
+            }
+        };
+        if config.has_changed().unwrap_or(false) {
+            limits = config.borrow_and_update().limits.clone();
+            rate_limiter.update(&limits);
+        }
+        match rate_limiter.check(&msg, Instant::now()) {
+            Verdict::Allow => {
+                if sender
+                    .send(ClientMessageWrapper { id: my_id, msg })
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Verdict::Drop => log::debug!("rate limited message (id={})", my_id),
+            Verdict::Disconnect => {
+                log::warn!("disconnecting {} for sending too many messages", my_id);
+                disconnect(&tx, "Too many messages");
                 break;
             }
         }
     }
     log::debug!("user disconnected: {}", my_id);
-    game_server.write().await.users.remove(&my_id);
+    game_server.write().await.remove_user(my_id);
     broadcast(&game_server, ServerMessage::GoodBye(my_id)).await;
 }
 
-fn parse_message(msg: Message) -> Option<ClientMessage> {
+/// Control frames like ping and close aren't client messages and yield `Ok(None)`.
+fn parse_message(msg: Message) -> Result<Option<ClientMessage>, ErrorCode> {
     if msg.is_binary() {
         let msg = msg.into_bytes();
-        deserialize::<ClientMessage>(msg.as_slice()).ok()
+        deserialize::<ClientMessage>(msg.as_slice())
+            .map(Some)
+            .map_err(|e| {
+                log::debug!("failed to deserialize client message: {}", e);
+                ErrorCode::Malformed
+            })
+    } else if msg.is_text() {
+        Err(ErrorCode::NotBinary)
     } else {
-        None
+        Ok(None)
     }
 }
 
-async fn user_message(msg: ClientMessage, id: Uuid, game_server: &GameServer) {
+/// Logging in is only allowed once, everything else only after logging in.
+async fn is_allowed(msg: &ClientMessage, id: Uuid, game_server: &GameServer) -> bool {
+    let state = game_server.read().await;
     match msg {
-        ClientMessage::Connect { name } => {
-            let msg = ServerMessage::PlayerJoined { id, name };
-            for user in game_server.read().await.users.values() {
-                send_msg(&user.tx, &msg);
+        ClientMessage::Login { .. } | ClientMessage::Resume { .. } => !state.is_authenticated(id),
+        ClientMessage::Ping => true,
+        _ => {
+            let authenticated = state.is_authenticated(id);
+            if !authenticated {
+                state.send_to(id, &ServerMessage::AuthenticationRequired);
             }
+            authenticated
+        }
+    }
+}
+
+async fn user_message(msg: ClientMessage, id: Uuid, game_server: &GameServer) {
+    if !is_allowed(&msg, id, game_server).await {
+        return;
+    }
+    match msg {
+        ClientMessage::Login { name, password } => {
+            login(id, name, password, game_server).await;
+        }
+        ClientMessage::Resume { name, token } => {
+            game_server.write().await.resume(id, &name, &token);
         }
         ClientMessage::ChangeName { name } => {
-            if game_server
-                .read()
+            game_server.write().await.change_name(id, name);
+        }
+        ClientMessage::ChallengePlayer { name } => {
+            game_server.write().await.challenge(id, &name);
+        }
+        ClientMessage::AcceptChallenge { request_id } => {
+            game_server
+                .write()
                 .await
+                .answer_challenge(id, request_id, true);
+        }
+        ClientMessage::DenyChallenge { request_id } => {
+            game_server
+                .write()
+                .await
+                .answer_challenge(id, request_id, false);
+        }
+        ClientMessage::JoinQueue => {
+            let mut state = game_server.write().await;
+            if let Some(rating) = state
                 .users
-                .iter()
-                .find(|(_, user)| user.name.to_lowercase() == name.to_lowercase())
-                .is_some()
+                .get(&id)
+                .filter(|user| user.match_id.is_none() && !state.shutting_down)
+                .map(|user| user.rating)
             {
-                ServerMessage::NameNotAvailable { name };
-            } else {
-                broadcast(
-                    game_server,
-                    ServerMessage::PlayerChangedName { id, new_name: name },
-                )
-                .await;
+                state.matchmaker.enqueue(id, rating, Instant::now());
             }
         }
-        ClientMessage::ChallengePlayer { name } => {
-            if let Some((_, player)) = game_server
-                .read()
+        ClientMessage::LeaveQueue => {
+            game_server.write().await.matchmaker.remove(id);
+        }
+        ClientMessage::State {
+            match_id,
+            kills,
+            ticks,
+            moved,
+        } => {
+            game_server
+                .write()
                 .await
-                .users
-                .iter()
-                .find(|(_, user)| user.name.to_lowercase() == name.to_lowercase())
-            {
-                let request_id = Uuid::new_v4();
-                send_msg(
-                    &player.tx,
-                    &ServerMessage::ChallengeReceived {
-                        request_id: request_id.clone(),
-                        name: player.name.clone(),
-                    },
-                );
-                send_msg(&player.tx, &ServerMessage::RequestReceived { request_id });
+                .update_state(id, match_id, kills, ticks, moved);
+        }
+        ClientMessage::SendEnemy { index } => {
+            game_server.write().await.send_enemy(id, index);
+        }
+        ClientMessage::Died => {
+            game_server.write().await.forfeit(id);
+        }
+        ClientMessage::Rematch => {
+            game_server.write().await.rematch(id);
+        }
+        ClientMessage::BackToLobby => {
+            game_server.write().await.leave_results(id);
+        }
+        ClientMessage::Ping => {
+            game_server.read().await.send_to(id, &ServerMessage::Pong);
+        }
+    }
+}
+
+/// Logs into the account with the given name, registering it on first use. Hashing the
+/// password is slow on purpose, so it happens without holding the lock.
+async fn login(id: Uuid, name: String, password: String, game_server: &GameServer) {
+    let known = {
+        let state = game_server.read().await;
+        match state.find_login(&name, &password) {
+            Ok(known) => known,
+            Err(reason) => {
+                state.login_failed(id, &reason);
+                return;
             }
         }
-        ClientMessage::AcceptChallenge { request_id } => todo!(),
-        ClientMessage::DenyChallenge { request_id } => todo!(),
-        ClientMessage::State { kills } => todo!(),
+    };
+    let checked = tokio::task::spawn_blocking(move || match known {
+        Some(account) => Ok(account.verify_password(&password).then_some(account)),
+        None => Account::new(name, &password, INITIAL_RATING).map(Some),
+    })
+    .await;
+    let mut state = game_server.write().await;
+    match checked {
+        Ok(Ok(Some(account))) => {
+            // the account might have changed while the password was checked
+            let account = state.storage.account(account.id).unwrap_or(account);
+            state.enter_lobby(id, account);
+        }
+        Ok(Ok(None)) => state.login_failed(id, "Wrong name or password"),
+        Ok(Err(e)) => {
+            log::error!("failed to create account: {}", e);
+            state.login_failed(id, "Internal server error");
+        }
+        Err(e) => {
+            log::error!("checking the password panicked: {}", e);
+            state.login_failed(id, "Internal server error");
+        }
     }
 }
 
@@ -202,12 +1041,153 @@ async fn update_loop(mut rx: ClientChannelReceiver, game_server: GameServer) {
     }
 }
 
+async fn status(game_server: &GameServer, metrics: &Metrics) -> Status {
+    let state = game_server.read().await;
+    let users_in_game = state
+        .users
+        .values()
+        .filter(|user| user.match_id.is_some())
+        .count();
+    let users_in_lobby = state
+        .users
+        .values()
+        .filter(|user| user.account_id.is_some() && user.match_id.is_none())
+        .count();
+    Status {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_seconds: metrics.started.elapsed().as_secs(),
+        connected_users: state.users.len(),
+        users_in_lobby,
+        users_in_game,
+        queued_users: state.matchmaker.len(),
+        active_matches: state.matches.len(),
+        pending_challenges: state.challenges.len(),
+        messages_received: metrics.messages_received.load(Ordering::Relaxed),
+        messages_sent: metrics.messages_sent.load(Ordering::Relaxed),
+        malformed_messages: metrics.malformed_messages.load(Ordering::Relaxed),
+    }
+}
+
+async fn matchmaking_loop(game_server: GameServer) {
+    loop {
+        let interval = game_server
+            .read()
+            .await
+            .config
+            .borrow()
+            .timeouts
+            .matchmaking_interval();
+        tokio::time::sleep(interval).await;
+        let mut state = game_server.write().await;
+        state.expire_rematches(false);
+        state.expire_challenges();
+        if state.matchmaker.len() < 2 {
+            continue;
+        }
+        for (first, second) in state.matchmaker.find_pairs(Instant::now()) {
+            state.start_match([first, second]);
+        }
+    }
+}
+
+async fn save_loop(game_server: GameServer) {
+    loop {
+        let interval = game_server
+            .read()
+            .await
+            .config
+            .borrow()
+            .timeouts
+            .save_interval();
+        tokio::time::sleep(interval).await;
+        game_server.write().await.save();
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            log::error!("failed to listen for ctrl+c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                log::error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => (),
+        () = terminate => (),
+    }
+}
+
+/// Stops new matches from starting and gives the running ones time to finish,
+/// then disconnects everyone and waits for the storage to be written.
+async fn drain(game_server: &GameServer) {
+    let timeout = game_server.read().await.config.borrow().timeouts.shutdown();
+    let deadline = Instant::now() + timeout;
+    {
+        let mut state = game_server.write().await;
+        log::info!(
+            "shutting down, waiting for {} matches to finish",
+            state.matches.len()
+        );
+        state.shutting_down = true;
+        state.matchmaker = Matchmaker::default();
+        state.cancel_challenges(|_| true);
+        state.expire_rematches(true);
+        state.broadcast(&ServerMessage::ShuttingDown {
+            seconds: timeout.as_secs(),
+        });
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let state = game_server.read().await;
+        let remaining = deadline.saturating_duration_since(Instant::now()).as_secs();
+        if state.matches.is_empty() || remaining == 0 {
+            break;
+        }
+        if remaining % 10 == 0 || remaining <= 5 {
+            state.broadcast(&ServerMessage::ShuttingDown { seconds: remaining });
+        }
+    }
+    let mut state = game_server.write().await;
+    state.abort_matches();
+    for user in state.users.values() {
+        disconnect(&user.tx, "Server shutting down");
+    }
+    state.save();
+    let saves = state.saves.clone();
+    drop(state);
+    saves.flushed().await;
+}
+
 #[derive(Parser)]
 struct Arguments {
+    /// Overrides `listen` from the config file
     #[arg(short, long)]
     listen: Option<String>,
+    /// Makes match seeds and maps the same on every run
     #[arg(short, long)]
-    seed: Option<usize>,
+    seed: Option<u64>,
+    /// Overrides `storage` from the config file
+    #[arg(long)]
+    storage: Option<PathBuf>,
+    /// Reloaded whenever it changes
+    #[arg(short, long, default_value = "server.toml")]
+    config: PathBuf,
 }
 
 #[tokio::main]
@@ -217,17 +1197,64 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Arguments::parse();
 
-    let status = warp::path!("status").map(move || warp::reply::html("hello"));
+    let mut initial_config = Config::load_or_default(&args.config)?;
+    if let Some(listen) = args.listen {
+        initial_config.listen = listen;
+    }
+    if let Some(storage) = args.storage {
+        initial_config.storage = storage;
+    }
+    let listen = initial_config.listen.parse::<SocketAddr>()?;
+    let storage = FileStorage::open(&initial_config.storage)?;
+    let channel_capacity = initial_config.limits.channel_capacity;
+    let (config_file, config) = ConfigFile::new(args.config, initial_config);
+    let _watcher = config_file.watch()?;
 
-    let game_server = GameServer::default();
+    let (saves, saver) = Saver::new();
+    tokio::spawn(saver.run());
+    let game_server = GameServer::new(RwLock::new(GameServerState::new(
+        Box::new(storage),
+        saves,
+        config.clone(),
+        args.seed
+            .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+    )));
     let seed: u64 = rand::random();
 
     let arc_game_server = game_server.clone();
 
-    let (sender, receiver) = mpsc::unbounded_channel();
+    let metrics = Arc::new(Metrics::default());
+    let (sender, receiver) = mpsc::channel(channel_capacity);
 
     tokio::spawn(async move { update_loop(receiver, arc_game_server).await });
 
+    let arc_game_server = game_server.clone();
+    tokio::spawn(async move { matchmaking_loop(arc_game_server).await });
+
+    let arc_game_server = game_server.clone();
+    tokio::spawn(async move { save_loop(arc_game_server).await });
+
+    let shutdown_game_server = game_server.clone();
+
+    let admin_routes = admin::routes(game_server.clone(), config.clone(), config_file);
+
+    let status_state = (game_server.clone(), metrics.clone());
+    let status_route = warp::path!("status").then(move || {
+        let (game_server, metrics) = status_state.clone();
+        async move { warp::reply::json(&status(&game_server, &metrics).await) }
+    });
+    let metrics_state = (game_server.clone(), metrics.clone());
+    let metrics_route = warp::path!("metrics").then(move || {
+        let (game_server, metrics) = metrics_state.clone();
+        async move {
+            warp::reply::with_header(
+                status(&game_server, &metrics).await.to_prometheus(),
+                "content-type",
+                "text/plain; version=0.0.4",
+            )
+        }
+    });
+
     let game_server = warp::any().map(move || game_server.clone());
     let seed = warp::any().map(move || seed);
 
@@ -235,18 +1262,411 @@ async fn main() -> anyhow::Result<()> {
         .and(warp::ws())
         .and(game_server)
         .and(seed)
-        .map(move |ws: warp::ws::Ws, game_server, seed| {
-            let sender = sender.clone();
-            ws.on_upgrade(move |socket| user_connected(socket, sender, game_server, seed))
-        });
-    let routes = status.or(game);
-    warp::serve(routes)
-        .run(
-            args.listen
-                .unwrap_or_else(|| "127.0.0.1:3030".to_owned())
-                .parse::<SocketAddr>()?,
-        )
-        .await;
+        .and(warp::addr::remote())
+        .map(
+            move |ws: warp::ws::Ws, game_server, seed, address: Option<SocketAddr>| {
+                let sender = sender.clone();
+                let config = config.clone();
+                let max_message_size = config.borrow().limits.max_message_size;
+                let metrics = metrics.clone();
+                ws.max_message_size(max_message_size)
+                    .max_frame_size(max_message_size)
+                    .on_upgrade(move |socket| {
+                        user_connected(
+                            socket,
+                            sender,
+                            game_server,
+                            seed,
+                            address.map(|address| address.ip()),
+                            config,
+                            metrics,
+                        )
+                    })
+            },
+        );
+    let routes = status_route
+        .or(metrics_route)
+        .or(admin_routes)
+        .or(game)
+        .recover(admin::handle_rejection);
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+    let (address, server) = warp::serve(routes).bind_with_graceful_shutdown(listen, async {
+        stop_rx.await.ok();
+    });
+    let server = tokio::spawn(server);
+    log::info!("listening on {}", address);
+
+    shutdown_signal().await;
+    drain(&shutdown_game_server).await;
+    stop_tx.send(()).ok();
+    server.await?;
+    log::info!("shutdown complete");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::MemoryStorage;
+    use tokio::sync::watch;
+
+    struct Client {
+        id: Uuid,
+        rx: mpsc::UnboundedReceiver<std::result::Result<Message, warp::Error>>,
+    }
+
+    impl Client {
+        /// Everything the server sent since the last call.
+        fn received(&mut self) -> Vec<ServerMessage> {
+            let mut messages = Vec::new();
+            while let Ok(Ok(msg)) = self.rx.try_recv() {
+                if msg.is_binary() {
+                    messages.push(deserialize(msg.as_bytes()).unwrap());
+                }
+            }
+            messages
+        }
+    }
+
+    fn server() -> GameServerState {
+        let (_, config) = watch::channel(Config::default());
+        let (saves, _) = Saver::new();
+        GameServerState::new(
+            Box::new(MemoryStorage::default()),
+            saves,
+            config,
+            StdRng::seed_from_u64(0),
+        )
+    }
+
+    /// Connects and logs into a fresh account.
+    fn connect(state: &mut GameServerState, name: &str) -> Client {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let id = Uuid::new_v4();
+        state.users.insert(
+            id,
+            User {
+                tx,
+                kicked: Arc::new(Notify::new()),
+                address: None,
+                account_id: None,
+                match_id: None,
+                name: String::new(),
+                rating: INITIAL_RATING,
+            },
+        );
+        let account = Account::new(name.to_owned(), "secret", INITIAL_RATING).unwrap();
+        state.enter_lobby(id, account);
+        let mut client = Client { id, rx };
+        client.received();
+        client
+    }
+
+    fn request_id(messages: &[ServerMessage]) -> Option<Uuid> {
+        messages.iter().find_map(|msg| match msg {
+            ServerMessage::ChallengeReceived { request_id, .. } => Some(*request_id),
+            _ => None,
+        })
+    }
+
+    fn match_id(messages: &[ServerMessage]) -> Option<Uuid> {
+        messages.iter().find_map(|msg| match msg {
+            ServerMessage::MatchStarted { match_id, .. } => Some(*match_id),
+            _ => None,
+        })
+    }
+
+    fn denied(messages: &[ServerMessage]) -> bool {
+        messages
+            .iter()
+            .any(|msg| matches!(msg, ServerMessage::ChallengeDenied { .. }))
+    }
+
+    fn finish(messages: Vec<ServerMessage>) -> Option<ServerMessage> {
+        messages
+            .into_iter()
+            .find(|msg| matches!(msg, ServerMessage::Finish { .. }))
+    }
+
+    #[test]
+    fn match_seeds_and_maps_follow_the_server_seed() {
+        let started: Vec<_> = (0..2)
+            .map(|_| {
+                let mut state = server();
+                let mut merlin = connect(&mut state, "Merlin");
+                let morgana = connect(&mut state, "Morgana");
+                assert!(state.start_match([merlin.id, morgana.id]));
+                merlin.received().into_iter().find_map(|msg| match msg {
+                    ServerMessage::MatchStarted { seed, settings, .. } => {
+                        Some((seed, settings.arena.map))
+                    }
+                    _ => None,
+                })
+            })
+            .collect();
+        assert!(started[0].is_some());
+        assert_eq!(started[0], started[1]);
+    }
+
+    #[test]
+    fn accepted_challenges_start_a_match() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let mut morgana = connect(&mut state, "Morgana");
+        state.challenge(merlin.id, "morgana");
+        let request_id = request_id(&morgana.received()).unwrap();
+        state.answer_challenge(morgana.id, request_id, true);
+        assert!(match_id(&merlin.received()).is_some());
+        assert!(match_id(&morgana.received()).is_some());
+        assert!(state.challenges.is_empty());
+        assert_eq!(state.matches.len(), 1);
+    }
+
+    #[test]
+    fn denied_challenges_tell_the_challenger() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let mut morgana = connect(&mut state, "Morgana");
+        state.challenge(merlin.id, "Morgana");
+        let request_id = request_id(&morgana.received()).unwrap();
+        // only the challenged player can answer
+        state.answer_challenge(merlin.id, request_id, true);
+        assert!(state.matches.is_empty());
+        state.answer_challenge(morgana.id, request_id, false);
+        assert!(denied(&merlin.received()));
+        assert!(state.matches.is_empty());
+    }
+
+    #[test]
+    fn challenges_to_self_or_busy_players_are_denied_right_away() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let morgana = connect(&mut state, "Morgana");
+        let mut arthur = connect(&mut state, "Arthur");
+        state.challenge(merlin.id, "Merlin");
+        assert!(denied(&merlin.received()));
+        assert!(state.start_match([morgana.id, arthur.id]));
+        state.challenge(merlin.id, "Arthur");
+        assert!(denied(&merlin.received()));
+        assert!(request_id(&arthur.received()).is_none());
+        assert!(state.challenges.is_empty());
+    }
+
+    #[test]
+    fn kills_from_another_match_are_ignored() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let morgana = connect(&mut state, "Morgana");
+        assert!(state.start_match([merlin.id, morgana.id]));
+        let match_id = match_id(&merlin.received()).unwrap();
+        state.update_state(merlin.id, Uuid::new_v4(), 12, 0, Vec2::ZERO);
+        assert_eq!(state.matches[&match_id].kills, [0, 0]);
+        state.update_state(merlin.id, match_id, 3, 0, Vec2::ZERO);
+        assert_eq!(state.matches[&match_id].kills, [3, 0]);
+    }
+
+    #[test]
+    fn states_of_players_moving_too_fast_are_ignored() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let morgana = connect(&mut state, "Morgana");
+        assert!(state.start_match([merlin.id, morgana.id]));
+        let match_id = match_id(&merlin.received()).unwrap();
+        let game = state.matches.get_mut(&match_id).unwrap();
+        game.started -= Duration::from_secs(2);
+        let settings = game.settings.clone();
+        let boosted = settings.movement.max_speed * settings.pickups.speed_multiplier;
+        let tick_rate = u32::try_from(settings.tick_rate).unwrap();
+        // a second at full speed
+        state.update_state(merlin.id, match_id, 2, tick_rate, Vec2::new(boosted, 0.));
+        assert_eq!(state.matches[&match_id].kills, [2, 0]);
+        // twice as far in the next second
+        let moved = Vec2::new(boosted, boosted * 2.);
+        state.update_state(merlin.id, match_id, 5, tick_rate * 2, moved);
+        assert_eq!(state.matches[&match_id].kills, [2, 0]);
+        // more ticks than the two seconds the match has been running
+        let moved = Vec2::new(boosted, boosted);
+        state.update_state(merlin.id, match_id, 5, tick_rate * 4, moved);
+        assert_eq!(state.matches[&match_id].kills, [2, 0]);
+        // going back in time
+        state.update_state(merlin.id, match_id, 5, tick_rate / 2, Vec2::ZERO);
+        assert_eq!(state.matches[&match_id].kills, [2, 0]);
+        state.update_state(merlin.id, match_id, 5, tick_rate * 2, moved);
+        assert_eq!(state.matches[&match_id].kills, [5, 0]);
+    }
+
+    #[test]
+    fn dying_hands_the_win_to_the_opponent() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let mut morgana = connect(&mut state, "Morgana");
+        // dying outside of a match does nothing
+        state.forfeit(merlin.id);
+        assert!(morgana.received().is_empty());
+        assert!(state.start_match([merlin.id, morgana.id]));
+        merlin.received();
+        morgana.received();
+        state.forfeit(merlin.id);
+        assert!(state.matches.is_empty());
+        assert!(state.users[&merlin.id].match_id.is_none());
+        let Some(ServerMessage::Finish {
+            winner,
+            rating_change,
+            ..
+        }) = finish(morgana.received())
+        else {
+            panic!("no results for the winner");
+        };
+        assert_eq!(winner, Some(morgana.id));
+        assert!(rating_change > 0);
+        let Some(ServerMessage::Finish { rating_change, .. }) = finish(merlin.received()) else {
+            panic!("no results for the loser");
+        };
+        assert!(rating_change < 0);
+        assert!(state.users[&morgana.id].rating > state.users[&merlin.id].rating);
+    }
+
+    #[test]
+    fn results_are_from_the_point_of_view_of_each_player() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let mut morgana = connect(&mut state, "Morgana");
+        assert!(state.start_match([merlin.id, morgana.id]));
+        let match_id = match_id(&merlin.received()).unwrap();
+        morgana.received();
+        let game = state.matches.get_mut(&match_id).unwrap();
+        game.kills = [7, 2];
+        game.sent = [3, 1];
+        state.finish_match(match_id, merlin.id);
+        let Some(ServerMessage::Finish {
+            enemy_kills,
+            enemies_sent,
+            enemies_received,
+            rematch_seconds,
+            ..
+        }) = finish(merlin.received())
+        else {
+            panic!("no results");
+        };
+        assert_eq!((enemy_kills, enemies_sent, enemies_received), (2, 3, 1));
+        assert_eq!(rematch_seconds, Config::default().timeouts.rematch_seconds);
+        let Some(ServerMessage::Finish {
+            enemy_kills,
+            enemies_sent,
+            enemies_received,
+            ..
+        }) = finish(morgana.received())
+        else {
+            panic!("no results");
+        };
+        assert_eq!((enemy_kills, enemies_sent, enemies_received), (7, 1, 3));
+    }
+
+    #[test]
+    fn aborted_matches_have_no_winner_or_rematch() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let morgana = connect(&mut state, "Morgana");
+        assert!(state.start_match([merlin.id, morgana.id]));
+        let match_id = match_id(&merlin.received()).unwrap();
+        assert!(state.abort_match(match_id));
+        let Some(ServerMessage::Finish {
+            winner,
+            rating_change,
+            rematch_seconds,
+            ..
+        }) = finish(merlin.received())
+        else {
+            panic!("no results");
+        };
+        assert_eq!((winner, rating_change, rematch_seconds), (None, 0, 0));
+        assert!(state.rematches.is_empty());
+        assert!(state.is_available(merlin.id));
+    }
+
+    /// Plays a match between both players that the first one wins.
+    fn play(state: &mut GameServerState, winner: &mut Client, loser: &mut Client) {
+        assert!(state.start_match([winner.id, loser.id]));
+        let match_id = match_id(&winner.received()).unwrap();
+        loser.received();
+        state.finish_match(match_id, winner.id);
+        winner.received();
+        loser.received();
+    }
+
+    fn cancelled(messages: &[ServerMessage]) -> Option<bool> {
+        messages.iter().find_map(|msg| match msg {
+            ServerMessage::RematchCancelled { expired } => Some(*expired),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn rematches_start_once_both_want_one() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let mut morgana = connect(&mut state, "Morgana");
+        play(&mut state, &mut merlin, &mut morgana);
+        state.rematch(merlin.id);
+        assert!(matches!(
+            morgana.received()[..],
+            [ServerMessage::RematchOffered]
+        ));
+        // asking twice doesn't accept for the opponent
+        state.rematch(merlin.id);
+        assert!(state.matches.is_empty());
+        state.rematch(morgana.id);
+        assert!(match_id(&merlin.received()).is_some());
+        assert!(match_id(&morgana.received()).is_some());
+        assert!(state.rematches.is_empty());
+    }
+
+    #[test]
+    fn leaving_the_results_turns_the_rematch_down() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let mut morgana = connect(&mut state, "Morgana");
+        play(&mut state, &mut merlin, &mut morgana);
+        state.rematch(merlin.id);
+        state.leave_results(morgana.id);
+        assert_eq!(cancelled(&merlin.received()), Some(false));
+        assert!(state.rematches.is_empty());
+        // too late to accept
+        state.rematch(morgana.id);
+        assert!(state.matches.is_empty());
+    }
+
+    #[test]
+    fn expired_rematches_send_both_players_back() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let mut morgana = connect(&mut state, "Morgana");
+        play(&mut state, &mut merlin, &mut morgana);
+        state.expire_rematches(false);
+        assert!(merlin.received().is_empty());
+        state.expire_rematches(true);
+        assert_eq!(cancelled(&merlin.received()), Some(true));
+        assert_eq!(cancelled(&morgana.received()), Some(true));
+        assert!(state.rematches.is_empty());
+    }
+
+    #[test]
+    fn no_rematches_while_shutting_down() {
+        let mut state = server();
+        let mut merlin = connect(&mut state, "Merlin");
+        let mut morgana = connect(&mut state, "Morgana");
+        assert!(state.start_match([merlin.id, morgana.id]));
+        let match_id = match_id(&merlin.received()).unwrap();
+        state.shutting_down = true;
+        state.finish_match(match_id, merlin.id);
+        let Some(ServerMessage::Finish {
+            rematch_seconds, ..
+        }) = finish(morgana.received())
+        else {
+            panic!("no results");
+        };
+        assert_eq!(rematch_seconds, 0);
+        assert!(state.rematches.is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use shared::Uuid;
+use std::time::{Duration, Instant};
+
+pub const INITIAL_RATING: u32 = 1200;
+
+const K_FACTOR: f64 = 32.;
+const BASE_WINDOW: f64 = 50.;
+const WINDOW_GROWTH_PER_SECOND: f64 = 10.;
+const MAX_WINDOW: f64 = 500.;
+
+struct QueueEntry {
+    id: Uuid,
+    rating: u32,
+    since: Instant,
+}
+
+/// Players waiting for a ranked match, oldest first.
+#[derive(Default)]
+pub struct Matchmaker {
+    queue: Vec<QueueEntry>,
+}
+
+impl Matchmaker {
+    pub fn enqueue(&mut self, id: Uuid, rating: u32, now: Instant) {
+        if !self.contains(id) {
+            self.queue.push(QueueEntry {
+                id,
+                rating,
+                since: now,
+            });
+        }
+    }
+
+    pub fn remove(&mut self, id: Uuid) -> bool {
+        let len = self.queue.len();
+        self.queue.retain(|entry| entry.id != id);
+        len != self.queue.len()
+    }
+
+    pub fn contains(&self, id: Uuid) -> bool {
+        self.queue.iter().any(|entry| entry.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Pairs up queued players whose ratings are within both of their search windows.
+    /// The players who waited the longest get matched first.
+    pub fn find_pairs(&mut self, now: Instant) -> Vec<(Uuid, Uuid)> {
+        let mut pairs = Vec::new();
+        let mut matched = vec![false; self.queue.len()];
+        for i in 0..self.queue.len() {
+            if matched[i] {
+                continue;
+            }
+            let entry = &self.queue[i];
+            let window = search_window(now.duration_since(entry.since));
+            let best = self
+                .queue
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .filter(|(j, _)| !matched[*j])
+                .map(|(j, other)| (j, entry.rating.abs_diff(other.rating)))
+                .filter(|(j, diff)| {
+                    let other_window = search_window(now.duration_since(self.queue[*j].since));
+                    f64::from(*diff) <= window.min(other_window)
+                })
+                .min_by_key(|(_, diff)| *diff);
+            if let Some((j, _)) = best {
+                matched[i] = true;
+                matched[j] = true;
+                pairs.push((entry.id, self.queue[j].id));
+            }
+        }
+        let mut matched = matched.into_iter();
+        self.queue.retain(|_| !matched.next().unwrap_or(false));
+        pairs
+    }
+}
+
+/// The maximum rating difference a player accepts after waiting for `waited`.
+pub fn search_window(waited: Duration) -> f64 {
+    (BASE_WINDOW + waited.as_secs_f64() * WINDOW_GROWTH_PER_SECOND).min(MAX_WINDOW)
+}
+
+fn expected_score(rating: u32, opponent: u32) -> f64 {
+    1. / (1. + 10f64.powf((f64::from(opponent) - f64::from(rating)) / 400.))
+}
+
+/// Returns the new ratings of the winner and the loser.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn update_ratings(winner: u32, loser: u32) -> (u32, u32) {
+    let change = K_FACTOR * (1. - expected_score(winner, loser));
+    let winner = (f64::from(winner) + change).round().max(0.) as u32;
+    let loser = (f64::from(loser) - change).round().max(0.) as u32;
+    (winner, loser)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_ratings_trade_half_the_k_factor() {
+        assert_eq!(update_ratings(1200, 1200), (1216, 1184));
+    }
+
+    #[test]
+    fn upsets_move_ratings_more() {
+        let (favourite, _) = update_ratings(1600, 1200);
+        let (underdog, _) = update_ratings(1200, 1600);
+        assert!(favourite - 1600 < underdog - 1200);
+    }
+
+    #[test]
+    fn ratings_never_drop_below_zero() {
+        assert_eq!(update_ratings(20, 0).1, 0);
+    }
+
+    #[test]
+    fn search_window_grows_up_to_the_max() {
+        assert!((search_window(Duration::ZERO) - BASE_WINDOW).abs() < f64::EPSILON);
+        assert!((search_window(Duration::from_secs(5)) - 100.).abs() < f64::EPSILON);
+        assert!((search_window(Duration::from_secs(3600)) - MAX_WINDOW).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn pairs_players_within_their_windows() {
+        let now = Instant::now();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut matchmaker = Matchmaker::default();
+        matchmaker.enqueue(a, 1200, now);
+        matchmaker.enqueue(b, 1400, now);
+        matchmaker.enqueue(c, 1230, now);
+        assert_eq!(matchmaker.find_pairs(now), vec![(a, c)]);
+        assert_eq!(matchmaker.len(), 1);
+        assert!(matchmaker.contains(b));
+    }
+
+    #[test]
+    fn waiting_widens_the_window() {
+        let start = Instant::now();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut matchmaker = Matchmaker::default();
+        matchmaker.enqueue(a, 1200, start);
+        matchmaker.enqueue(b, 1300, start);
+        assert!(matchmaker.find_pairs(start).is_empty());
+        assert_eq!(
+            matchmaker.find_pairs(start + Duration::from_secs(5)),
+            vec![(a, b)]
+        );
+    }
+
+    #[test]
+    fn enqueue_ignores_players_already_queued() {
+        let now = Instant::now();
+        let id = Uuid::new_v4();
+        let mut matchmaker = Matchmaker::default();
+        matchmaker.enqueue(id, 1200, now);
+        matchmaker.enqueue(id, 1200, now);
+        assert_eq!(matchmaker.len(), 1);
+        assert!(matchmaker.remove(id));
+        assert!(!matchmaker.remove(id));
+    }
+}
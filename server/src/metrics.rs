@@ -0,0 +1,153 @@
+use serde::Serialize;
+use std::{fmt::Write, sync::atomic::AtomicU64, time::Instant};
+
+/// Counters shared by all connections, updated without locking the game state.
+pub struct Metrics {
+    pub started: Instant,
+    pub messages_received: AtomicU64,
+    pub messages_sent: AtomicU64,
+    pub malformed_messages: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            messages_received: AtomicU64::default(),
+            messages_sent: AtomicU64::default(),
+            malformed_messages: AtomicU64::default(),
+        }
+    }
+}
+
+/// A snapshot of the server, served as json on `/status` and in the prometheus format on `/metrics`.
+#[derive(Serialize)]
+pub struct Status {
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    pub connected_users: usize,
+    pub users_in_lobby: usize,
+    pub users_in_game: usize,
+    pub queued_users: usize,
+    pub active_matches: usize,
+    pub pending_challenges: usize,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    pub malformed_messages: u64,
+}
+
+impl Status {
+    pub fn to_prometheus(&self) -> String {
+        let gauges = [
+            (
+                "uptime_seconds",
+                "Seconds since the server started",
+                self.uptime_seconds,
+            ),
+            (
+                "connected_users",
+                "Open websocket connections",
+                self.connected_users as u64,
+            ),
+            (
+                "users_in_lobby",
+                "Logged in users not in a match",
+                self.users_in_lobby as u64,
+            ),
+            (
+                "users_in_game",
+                "Users playing a match",
+                self.users_in_game as u64,
+            ),
+            (
+                "queued_users",
+                "Users waiting for a ranked match",
+                self.queued_users as u64,
+            ),
+            (
+                "active_matches",
+                "Matches being played",
+                self.active_matches as u64,
+            ),
+            (
+                "pending_challenges",
+                "Challenges waiting for an answer",
+                self.pending_challenges as u64,
+            ),
+        ];
+        let counters = [
+            (
+                "messages_received_total",
+                "Messages received from clients",
+                self.messages_received,
+            ),
+            (
+                "messages_sent_total",
+                "Messages sent to clients",
+                self.messages_sent,
+            ),
+            (
+                "malformed_messages_total",
+                "Messages from clients that could not be decoded",
+                self.malformed_messages,
+            ),
+        ];
+        let mut out = String::new();
+        for (kind, metrics) in [("gauge", &gauges[..]), ("counter", &counters[..])] {
+            for (name, help, value) in metrics {
+                writeln!(out, "# HELP mage_battle_{name} {help}").ok();
+                writeln!(out, "# TYPE mage_battle_{name} {kind}").ok();
+                writeln!(out, "mage_battle_{name} {value}").ok();
+            }
+        }
+        writeln!(
+            out,
+            "# HELP mage_battle_build_info The version of the server\n\
+             # TYPE mage_battle_build_info gauge\n\
+             mage_battle_build_info{{version=\"{}\"}} 1",
+            self.version
+        )
+        .ok();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Status {
+        Status {
+            version: "1.2.3",
+            uptime_seconds: 42,
+            connected_users: 5,
+            users_in_lobby: 2,
+            users_in_game: 2,
+            queued_users: 1,
+            active_matches: 1,
+            pending_challenges: 0,
+            messages_received: 100,
+            messages_sent: 200,
+            malformed_messages: 3,
+        }
+    }
+
+    #[test]
+    fn every_metric_has_help_type_and_value() {
+        let text = status().to_prometheus();
+        assert!(text.contains(
+            "# HELP mage_battle_connected_users Open websocket connections\n\
+             # TYPE mage_battle_connected_users gauge\n\
+             mage_battle_connected_users 5\n"
+        ));
+        assert!(text.contains(
+            "# TYPE mage_battle_messages_sent_total counter\n\
+             mage_battle_messages_sent_total 200\n"
+        ));
+        assert!(text.ends_with("mage_battle_build_info{version=\"1.2.3\"} 1\n"));
+        let samples = text.lines().filter(|line| !line.starts_with('#')).count();
+        assert_eq!(samples, 11);
+        assert_eq!(text.matches("# HELP").count(), samples);
+        assert_eq!(text.matches("# TYPE").count(), samples);
+    }
+}
@@ -0,0 +1,531 @@
+use crate::auth;
+use serde::{Deserialize, Serialize};
+use shared::Uuid;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Account {
+    pub id: Uuid,
+    pub name: String,
+    pub rating: u32,
+    pub wins: u32,
+    pub losses: u32,
+    /// Includes the salt, see [`auth::hash_password`].
+    pub password_hash: String,
+    pub token_hash: Option<String>,
+    /// Seconds since the unix epoch after which the token isn't accepted anymore.
+    #[serde(default)]
+    pub token_expires_at: u64,
+}
+
+impl Account {
+    /// Hashes the password, which is slow on purpose.
+    pub fn new(name: String, password: &str, rating: u32) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: Uuid::new_v4(),
+            name,
+            rating,
+            wins: 0,
+            losses: 0,
+            password_hash: auth::hash_password(password)?,
+            token_hash: None,
+            token_expires_at: 0,
+        })
+    }
+
+    /// As slow as [`Account::new`].
+    pub fn verify_password(&self, password: &str) -> bool {
+        auth::verify_password(password, &self.password_hash)
+    }
+
+    /// Replaces the session token with a new one that is valid for `lifetime`.
+    pub fn issue_token(&mut self, lifetime: Duration) -> String {
+        let token = auth::new_token();
+        self.token_hash = Some(auth::hash_token(&token));
+        self.token_expires_at = unix_time() + lifetime.as_secs();
+        token
+    }
+
+    pub fn verify_token(&self, token: &str) -> bool {
+        unix_time() < self.token_expires_at
+            && self
+                .token_hash
+                .as_ref()
+                .is_some_and(|hash| auth::hashes_match(&auth::hash_token(token), hash))
+    }
+}
+
+/// Seconds since the unix epoch.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MatchRecord {
+    pub id: Uuid,
+    pub winner: Uuid,
+    pub loser: Uuid,
+    pub winner_kills: usize,
+    pub loser_kills: usize,
+    /// Seconds since the unix epoch.
+    pub finished_at: u64,
+}
+
+impl MatchRecord {
+    pub fn new(
+        id: Uuid,
+        (winner, winner_kills): (Uuid, usize),
+        (loser, loser_kills): (Uuid, usize),
+    ) -> Self {
+        Self {
+            id,
+            winner,
+            loser,
+            winner_kills,
+            loser_kills,
+            finished_at: unix_time(),
+        }
+    }
+}
+
+/// Everything about players that has to survive a server restart.
+pub trait Storage: Send + Sync {
+    fn account(&self, id: Uuid) -> Option<Account>;
+    /// Looks up an account by name, ignoring case.
+    fn account_by_name(&self, name: &str) -> Option<Account>;
+    fn save_account(&mut self, account: &Account) -> anyhow::Result<()>;
+    fn record_match(&mut self, record: MatchRecord) -> anyhow::Result<()>;
+    /// The matches the account played, oldest first.
+    fn match_history(&self, account: Uuid) -> Vec<MatchRecord>;
+    fn match_count(&self, account: Uuid) -> usize {
+        self.match_history(account).len()
+    }
+    fn is_banned(&self, account: Uuid) -> bool;
+    fn set_banned(&mut self, account: Uuid, banned: bool) -> anyhow::Result<()>;
+    fn is_address_banned(&self, address: IpAddr) -> bool;
+    fn set_address_banned(&mut self, address: IpAddr, banned: bool) -> anyhow::Result<()>;
+    /// Takes the changes that still have to be written, `None` if there are none.
+    /// Meant to be called under the lock of the game state and written after letting go of it.
+    fn snapshot(&mut self) -> anyhow::Result<Option<Snapshot>>;
+}
+
+/// Keeps everything in memory, used for tests and as the state of [`FileStorage`].
+#[derive(Default, Deserialize, Serialize)]
+pub struct MemoryStorage {
+    accounts: HashMap<Uuid, Account>,
+    /// [`FileStorage`] keeps these in its match log instead.
+    #[serde(skip)]
+    matches: Vec<MatchRecord>,
+    #[serde(default)]
+    banned_accounts: HashSet<Uuid>,
+    #[serde(default)]
+    banned_addresses: HashSet<IpAddr>,
+}
+
+impl Storage for MemoryStorage {
+    fn account(&self, id: Uuid) -> Option<Account> {
+        self.accounts.get(&id).cloned()
+    }
+
+    fn account_by_name(&self, name: &str) -> Option<Account> {
+        let name = name.to_lowercase();
+        self.accounts
+            .values()
+            .find(|account| account.name.to_lowercase() == name)
+            .cloned()
+    }
+
+    fn save_account(&mut self, account: &Account) -> anyhow::Result<()> {
+        self.accounts.insert(account.id, account.clone());
+        Ok(())
+    }
+
+    fn record_match(&mut self, record: MatchRecord) -> anyhow::Result<()> {
+        self.matches.push(record);
+        Ok(())
+    }
+
+    fn match_history(&self, account: Uuid) -> Vec<MatchRecord> {
+        self.matches
+            .iter()
+            .filter(|record| record.winner == account || record.loser == account)
+            .cloned()
+            .collect()
+    }
+
+    fn is_banned(&self, account: Uuid) -> bool {
+        self.banned_accounts.contains(&account)
+    }
+
+    fn set_banned(&mut self, account: Uuid, banned: bool) -> anyhow::Result<()> {
+        if banned {
+            self.banned_accounts.insert(account);
+        } else {
+            self.banned_accounts.remove(&account);
+        }
+        Ok(())
+    }
+
+    fn is_address_banned(&self, address: IpAddr) -> bool {
+        self.banned_addresses.contains(&address)
+    }
+
+    fn set_address_banned(&mut self, address: IpAddr, banned: bool) -> anyhow::Result<()> {
+        if banned {
+            self.banned_addresses.insert(address);
+        } else {
+            self.banned_addresses.remove(&address);
+        }
+        Ok(())
+    }
+
+    fn snapshot(&mut self) -> anyhow::Result<Option<Snapshot>> {
+        Ok(None)
+    }
+}
+
+/// Persists accounts and bans as a json file that is replaced on every change, and
+/// appends finished matches to a log next to it, one json record per line.
+/// Changes are only kept in memory until the next [`Storage::snapshot`] is written.
+pub struct FileStorage {
+    path: PathBuf,
+    log_path: PathBuf,
+    data: MemoryStorage,
+    /// Whether there are changes to accounts or bans the file doesn't have yet.
+    dirty: bool,
+    /// Matches the log doesn't have yet.
+    unwritten: Vec<MatchRecord>,
+}
+
+impl FileStorage {
+    /// Loads the storage from `path` and the match log next to it,
+    /// starting out empty if they don't exist yet.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let log_path = path.with_extension("matches.jsonl");
+        let mut data: MemoryStorage = if path.exists() {
+            serde_json::from_slice(&fs::read(&path)?)?
+        } else {
+            MemoryStorage::default()
+        };
+        if log_path.exists() {
+            let log = fs::read_to_string(&log_path)?;
+            for line in log.lines().filter(|line| !line.is_empty()) {
+                // the last line is cut off if the server died while appending it
+                match serde_json::from_str::<MatchRecord>(line) {
+                    Ok(record) => data.matches.push(record),
+                    Err(e) => log::warn!("skipping broken match record: {}", e),
+                }
+            }
+            if !log.is_empty() && !log.ends_with('\n') {
+                // so the next record starts on a line of its own
+                OpenOptions::new()
+                    .append(true)
+                    .open(&log_path)?
+                    .write_all(b"\n")?;
+            }
+        }
+        Ok(Self {
+            path,
+            log_path,
+            data,
+            dirty: false,
+            unwritten: Vec::new(),
+        })
+    }
+}
+
+impl Storage for FileStorage {
+    fn account(&self, id: Uuid) -> Option<Account> {
+        self.data.account(id)
+    }
+
+    fn account_by_name(&self, name: &str) -> Option<Account> {
+        self.data.account_by_name(name)
+    }
+
+    fn save_account(&mut self, account: &Account) -> anyhow::Result<()> {
+        self.data.save_account(account)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn record_match(&mut self, record: MatchRecord) -> anyhow::Result<()> {
+        self.data.record_match(record.clone())?;
+        self.unwritten.push(record);
+        Ok(())
+    }
+
+    fn match_history(&self, account: Uuid) -> Vec<MatchRecord> {
+        self.data.match_history(account)
+    }
+
+    fn is_banned(&self, account: Uuid) -> bool {
+        self.data.is_banned(account)
+    }
+
+    fn set_banned(&mut self, account: Uuid, banned: bool) -> anyhow::Result<()> {
+        self.data.set_banned(account, banned)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn is_address_banned(&self, address: IpAddr) -> bool {
+        self.data.is_address_banned(address)
+    }
+
+    fn set_address_banned(&mut self, address: IpAddr, banned: bool) -> anyhow::Result<()> {
+        self.data.set_address_banned(address, banned)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn snapshot(&mut self) -> anyhow::Result<Option<Snapshot>> {
+        if !self.dirty && self.unwritten.is_empty() {
+            return Ok(None);
+        }
+        let state = if self.dirty {
+            Some(serde_json::to_vec(&self.data)?)
+        } else {
+            None
+        };
+        let mut matches = Vec::new();
+        for record in &self.unwritten {
+            serde_json::to_writer(&mut matches, record)?;
+            matches.push(b'\n');
+        }
+        self.dirty = false;
+        self.unwritten.clear();
+        Ok(Some(Snapshot {
+            path: self.path.clone(),
+            state,
+            log_path: self.log_path.clone(),
+            matches,
+        }))
+    }
+}
+
+/// Changes taken from a [`FileStorage`], ready to be written.
+pub struct Snapshot {
+    path: PathBuf,
+    /// Accounts and bans, if they changed.
+    state: Option<Vec<u8>>,
+    log_path: PathBuf,
+    /// Lines to append to the match log.
+    matches: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Blocks until everything is on disk.
+    pub fn write(&self) -> anyhow::Result<()> {
+        if !self.matches.is_empty() {
+            let mut log = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.log_path)?;
+            log.write_all(&self.matches)?;
+            log.sync_data()?;
+        }
+        if let Some(state) = &self.state {
+            let tmp = self.path.with_extension("tmp");
+            fs::write(&tmp, state)?;
+            fs::rename(tmp, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+enum SaveJob {
+    Write(Snapshot),
+    /// Answered once everything queued before it is written.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queues snapshots for the [`Saver`], which writes them in order.
+#[derive(Clone)]
+pub struct SaveQueue {
+    tx: mpsc::UnboundedSender<SaveJob>,
+}
+
+impl SaveQueue {
+    pub fn push(&self, snapshot: Snapshot) {
+        if self.tx.send(SaveJob::Write(snapshot)).is_err() {
+            log::error!("the storage saver stopped, changes are lost");
+        }
+    }
+
+    /// Waits for everything queued so far to be written.
+    pub async fn flushed(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(SaveJob::Flush(done_tx)).is_ok() {
+            done_rx.await.ok();
+        }
+    }
+}
+
+/// Writes snapshots on a blocking thread, so the game state isn't locked while the disk is busy.
+pub struct Saver {
+    rx: mpsc::UnboundedReceiver<SaveJob>,
+}
+
+impl Saver {
+    pub fn new() -> (SaveQueue, Self) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (SaveQueue { tx }, Self { rx })
+    }
+
+    pub async fn run(mut self) {
+        while let Some(job) = self.rx.recv().await {
+            match job {
+                SaveJob::Write(snapshot) => {
+                    match tokio::task::spawn_blocking(move || snapshot.write()).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => log::error!("failed to save storage: {}", e),
+                        Err(e) => log::error!("saving the storage panicked: {}", e),
+                    }
+                }
+                SaveJob::Flush(done) => {
+                    done.send(()).ok();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(storage: &mut dyn Storage) {
+        let account = Account::new("Merlin".to_owned(), "secret", 1200).unwrap();
+        let opponent = Account::new("Morgana".to_owned(), "secret", 1200).unwrap();
+        storage.save_account(&account).unwrap();
+        storage.save_account(&opponent).unwrap();
+        storage
+            .record_match(MatchRecord::new(
+                Uuid::new_v4(),
+                (account.id, 10),
+                (opponent.id, 4),
+            ))
+            .unwrap();
+        storage.set_banned(opponent.id, true).unwrap();
+        let address = IpAddr::from([127, 0, 0, 1]);
+        storage.set_address_banned(address, true).unwrap();
+
+        let loaded = storage.account_by_name("merlin").unwrap();
+        assert_eq!(loaded.id, account.id);
+        assert!(loaded.verify_password("secret"));
+        assert!(!loaded.verify_password("wrong"));
+        assert_eq!(storage.account(opponent.id).unwrap().name, "Morgana");
+        let history = storage.match_history(opponent.id);
+        assert_eq!(history.len(), 1);
+        assert_eq!(
+            (history[0].winner, history[0].winner_kills),
+            (account.id, 10)
+        );
+        assert_eq!(storage.match_count(account.id), 1);
+        assert!(storage.is_banned(opponent.id));
+        assert!(!storage.is_banned(account.id));
+        assert!(storage.is_address_banned(address));
+    }
+
+    #[test]
+    fn tokens_only_work_until_they_expire() {
+        let mut account = Account::new("Merlin".to_owned(), "secret", 1200).unwrap();
+        assert!(!account.verify_token(""));
+        let token = account.issue_token(Duration::from_secs(90));
+        assert!(account.verify_token(&token));
+        assert!(!account.verify_token(&auth::new_token()));
+        let token = account.issue_token(Duration::ZERO);
+        assert!(!account.verify_token(&token));
+    }
+
+    #[test]
+    fn memory_storage_round_trip() {
+        round_trip(&mut MemoryStorage::default());
+    }
+
+    #[test]
+    fn file_storage_round_trip() {
+        let path = std::env::temp_dir().join(format!("mage_battle_{}.json", Uuid::new_v4()));
+        let mut storage = FileStorage::open(&path).unwrap();
+        round_trip(&mut storage);
+        assert!(!path.exists());
+        storage.snapshot().unwrap().unwrap().write().unwrap();
+        assert!(storage.snapshot().unwrap().is_none());
+
+        let mut reopened = FileStorage::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension("matches.jsonl")).unwrap();
+        let account = reopened.account_by_name("Merlin").unwrap();
+        let history = reopened.match_history(account.id);
+        assert_eq!(history.len(), 1);
+        assert_eq!((history[0].winner, history[0].loser_kills), (account.id, 4));
+        assert!(reopened.is_address_banned(IpAddr::from([127, 0, 0, 1])));
+        assert!(reopened.snapshot().unwrap().is_none());
+    }
+
+    #[test]
+    fn matches_are_appended_without_rewriting_accounts() {
+        let path = std::env::temp_dir().join(format!("mage_battle_{}.json", Uuid::new_v4()));
+        let log_path = path.with_extension("matches.jsonl");
+        let mut storage = FileStorage::open(&path).unwrap();
+        let (winner, loser) = (Uuid::new_v4(), Uuid::new_v4());
+        for _ in 0..2 {
+            storage
+                .record_match(MatchRecord::new(Uuid::new_v4(), (winner, 3), (loser, 1)))
+                .unwrap();
+            storage.snapshot().unwrap().unwrap().write().unwrap();
+        }
+        assert!(!path.exists());
+        // a record cut off while it was written
+        OpenOptions::new()
+            .append(true)
+            .open(&log_path)
+            .unwrap()
+            .write_all(b"{\"id\":")
+            .unwrap();
+
+        let mut reopened = FileStorage::open(&path).unwrap();
+        assert_eq!(reopened.match_count(winner), 2);
+        reopened
+            .record_match(MatchRecord::new(Uuid::new_v4(), (loser, 5), (winner, 2)))
+            .unwrap();
+        reopened.snapshot().unwrap().unwrap().write().unwrap();
+
+        let reopened = FileStorage::open(&path).unwrap();
+        fs::remove_file(&log_path).unwrap();
+        assert_eq!(reopened.match_count(winner), 3);
+        assert_eq!(reopened.match_count(loser), 3);
+    }
+
+    #[tokio::test]
+    async fn saver_writes_everything_queued_before_flushing() {
+        let path = std::env::temp_dir().join(format!("mage_battle_{}.json", Uuid::new_v4()));
+        let mut storage = FileStorage::open(&path).unwrap();
+        let (saves, saver) = Saver::new();
+        tokio::spawn(saver.run());
+        for name in ["Merlin", "Morgana"] {
+            storage
+                .save_account(&Account::new(name.to_owned(), "secret", 1200).unwrap())
+                .unwrap();
+            saves.push(storage.snapshot().unwrap().unwrap());
+        }
+        saves.flushed().await;
+
+        let reopened = FileStorage::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(reopened.account_by_name("Merlin").is_some());
+        assert!(reopened.account_by_name("Morgana").is_some());
+    }
+}
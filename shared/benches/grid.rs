@@ -0,0 +1,77 @@
+//! Compares finding overlapping entities with the grid against checking every pair.
+//! Run with `cargo bench -p shared --bench grid`.
+
+use glam::Vec2;
+use shared::{Rng, SpatialGrid};
+use std::{hint::black_box, time::Instant};
+
+/// The size of the maps, in world units.
+const WORLD: (f32, f32) = (400., 288.);
+/// Half the size of an enemy.
+const HALF: f32 = 8.;
+const CELL: f32 = 32.;
+const ROUNDS: u32 = 10;
+
+fn overlaps(a: Vec2, b: Vec2) -> bool {
+    (a - b).abs().cmple(Vec2::splat(HALF * 2.)).all()
+}
+
+fn pairwise(centers: &[Vec2]) -> usize {
+    centers
+        .iter()
+        .enumerate()
+        .map(|(index, center)| {
+            centers
+                .iter()
+                .enumerate()
+                .filter(|(other, other_center)| {
+                    *other != index && overlaps(*center, **other_center)
+                })
+                .count()
+        })
+        .sum()
+}
+
+fn with_grid(grid: &mut SpatialGrid, centers: &[Vec2]) -> usize {
+    grid.clear();
+    for (index, center) in centers.iter().enumerate() {
+        grid.insert(index, *center, Vec2::splat(HALF));
+    }
+    centers
+        .iter()
+        .enumerate()
+        .map(|(index, center)| {
+            grid.query(*center, Vec2::splat(HALF))
+                .filter(|other| *other != index && overlaps(*center, centers[*other]))
+                .count()
+        })
+        .sum()
+}
+
+/// Average milliseconds per round.
+fn time(mut round: impl FnMut() -> usize) -> (f64, usize) {
+    let start = Instant::now();
+    let mut found = 0;
+    for _ in 0..ROUNDS {
+        found = black_box(round());
+    }
+    (
+        start.elapsed().as_secs_f64() * 1000. / f64::from(ROUNDS),
+        found,
+    )
+}
+
+fn main() {
+    let mut rng = Rng::new(1);
+    println!("entities   pairwise ms   grid ms");
+    for count in [100, 1_000, 5_000, 10_000, 20_000] {
+        let centers: Vec<Vec2> = (0..count)
+            .map(|_| Vec2::new(rng.range(0., WORLD.0), rng.range(0., WORLD.1)))
+            .collect();
+        let mut grid = SpatialGrid::new(Vec2::new(WORLD.0, WORLD.1), CELL);
+        let (pairwise_ms, expected) = time(|| pairwise(&centers));
+        let (grid_ms, found) = time(|| with_grid(&mut grid, &centers));
+        assert_eq!(expected, found, "the grid missed overlaps");
+        println!("{:>8} {:>13.3} {:>9.3}", count, pairwise_ms, grid_ms);
+    }
+}
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+/// How an enemy picks where to go, all of them keep some distance to each other.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Behaviour {
+    /// Finds the shortest way to the player around walls.
+    #[default]
+    Chase,
+    /// Walks in a random direction, picking a new one every now and then.
+    Wander,
+    /// Follows the player but backs off when getting close.
+    Kite,
+}
+
+/// A kind of enemy players can send to their opponent by spending kills.
+/// The long names are for config files.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct EnemyType {
+    #[serde(rename = "n", alias = "name")]
+    pub name: String,
+    /// How many kills it takes to send one.
+    #[serde(rename = "c", alias = "cost")]
+    pub cost: usize,
+    /// In world units per second.
+    #[serde(rename = "s", alias = "speed")]
+    pub speed: f32,
+    #[serde(rename = "h", alias = "health")]
+    pub health: u32,
+    /// How much health touching it costs the player.
+    #[serde(rename = "d", alias = "damage")]
+    pub damage: u32,
+    /// Name of the sprite sheet in the asset manifest of the client.
+    #[serde(rename = "sp", alias = "sprite")]
+    pub sprite: String,
+    #[serde(rename = "b", alias = "behaviour", default)]
+    pub behaviour: Behaviour,
+}
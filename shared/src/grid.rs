@@ -0,0 +1,124 @@
+use glam::Vec2;
+
+/// A uniform grid over the world that finds what might overlap a box without
+/// checking everything against everything. Entities are filed under the cell
+/// of their center, so queries look as far around as the largest entity reaches.
+#[derive(Debug, Clone)]
+pub struct SpatialGrid {
+    cell_size: f32,
+    columns: usize,
+    rows: usize,
+    /// Indices of the entities in each cell, row by row.
+    cells: Vec<Vec<usize>>,
+    /// Half of the largest entity inserted since the last clear.
+    largest: Vec2,
+}
+
+impl SpatialGrid {
+    /// Covers a world of `size` with square cells. Entities outside of it are kept
+    /// in the cells along the edge, so they are still found, only less quickly.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(size: Vec2, cell_size: f32) -> Self {
+        let columns = ((size.x / cell_size).ceil() as usize).max(1);
+        let rows = ((size.y / cell_size).ceil() as usize).max(1);
+        Self {
+            cell_size,
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+            largest: Vec2::ZERO,
+        }
+    }
+
+    /// Removes all entities, but keeps the memory for the next tick.
+    pub fn clear(&mut self) {
+        for cell in &mut self.cells {
+            cell.clear();
+        }
+        self.largest = Vec2::ZERO;
+    }
+
+    /// Files the entity `index` whose box reaches `half_size` around `center`.
+    pub fn insert(&mut self, index: usize, center: Vec2, half_size: Vec2) {
+        let (column, row) = self.cell(center);
+        self.cells[row * self.columns + column].push(index);
+        self.largest = self.largest.max(half_size);
+    }
+
+    /// The entities whose box might overlap the box reaching `half_size` around `center`,
+    /// each one once. Callers still have to check if they really do.
+    pub fn query(&self, center: Vec2, half_size: Vec2) -> impl Iterator<Item = usize> + '_ {
+        let reach = half_size + self.largest;
+        let (first_column, first_row) = self.cell(center - reach);
+        let (last_column, last_row) = self.cell(center + reach);
+        (first_row..=last_row)
+            .flat_map(move |row| {
+                (first_column..=last_column).map(move |column| row * self.columns + column)
+            })
+            .flat_map(move |cell| self.cells[cell].iter().copied())
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn cell(&self, point: Vec2) -> (usize, usize) {
+        // negative numbers saturate to 0 when cast
+        let column = ((point.x / self.cell_size) as usize).min(self.columns - 1);
+        let row = ((point.y / self.cell_size) as usize).min(self.rows - 1);
+        (column, row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(found: impl Iterator<Item = usize>) -> Vec<usize> {
+        let mut found: Vec<usize> = found.collect();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn finds_nearby_entities_only() {
+        let mut grid = SpatialGrid::new(Vec2::new(320., 320.), 32.);
+        grid.insert(0, Vec2::new(40., 40.), Vec2::splat(8.));
+        grid.insert(1, Vec2::new(60., 40.), Vec2::splat(8.));
+        grid.insert(2, Vec2::new(300., 300.), Vec2::splat(8.));
+        assert_eq!(
+            sorted(grid.query(Vec2::new(50., 40.), Vec2::splat(8.))),
+            vec![0, 1]
+        );
+        assert_eq!(
+            sorted(grid.query(Vec2::new(290., 290.), Vec2::splat(8.))),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn large_entities_reach_into_other_cells() {
+        let mut grid = SpatialGrid::new(Vec2::new(320., 320.), 32.);
+        grid.insert(0, Vec2::new(16., 16.), Vec2::splat(100.));
+        assert_eq!(
+            sorted(grid.query(Vec2::new(100., 16.), Vec2::splat(1.))),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn entities_outside_the_world_are_found() {
+        let mut grid = SpatialGrid::new(Vec2::new(64., 64.), 32.);
+        grid.insert(0, Vec2::new(-20., 500.), Vec2::splat(4.));
+        assert_eq!(
+            sorted(grid.query(Vec2::new(-20., 500.), Vec2::splat(4.))),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn clear_removes_everything() {
+        let mut grid = SpatialGrid::new(Vec2::new(64., 64.), 32.);
+        grid.insert(0, Vec2::new(10., 10.), Vec2::splat(4.));
+        grid.clear();
+        assert_eq!(grid.query(Vec2::new(10., 10.), Vec2::splat(64.)).count(), 0);
+    }
+}
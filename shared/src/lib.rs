@@ -1,10 +1,112 @@
 #![warn(clippy::pedantic, clippy::perf)]
 
+mod enemy;
+mod grid;
+mod movement;
+mod pickup;
+mod rng;
+
+pub use enemy::{Behaviour, EnemyType};
+pub use grid::SpatialGrid;
+pub use movement::Movement;
+pub use pickup::{PickupKind, Pickups};
+pub use rng::Rng;
 use serde::{Deserialize, Serialize};
 pub use uuid::Uuid;
 
-pub const SPEED: f32 = 1.;
 pub const TICKRATE: u64 = 64;
+pub const PLAYER_HEALTH: u32 = 10;
+pub const PLAYER_MANA: f32 = 100.;
+pub const INVULNERABLE_SECONDS: f32 = 1.;
+pub const BOLT_SPEED: f32 = 180.;
+pub const DEFAULT_MAP: &str = "open";
+/// Maps can't have smaller tiles, nothing may move this far in one tick or it could
+/// skip through a wall.
+pub const MIN_TILE_SIZE: f32 = 16.;
+
+/// What happens to players walking past the edge of the map.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Edges {
+    /// Leaving on one side enters on the opposite side.
+    #[default]
+    Wrap,
+    /// Players stop at the edge.
+    Clamp,
+}
+
+/// The playing field, its size comes from the map.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Arena {
+    /// Name of the map in the asset manifest of the client.
+    #[serde(rename = "m")]
+    pub map: String,
+    #[serde(rename = "e")]
+    pub edges: Edges,
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self {
+            map: DEFAULT_MAP.to_owned(),
+            edges: Edges::default(),
+        }
+    }
+}
+
+/// Gameplay parameters the server decides on per match.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MatchSettings {
+    #[serde(rename = "t")]
+    pub tick_rate: u64,
+    #[serde(rename = "mv")]
+    pub movement: Movement,
+    /// Health players start the match with.
+    #[serde(rename = "h")]
+    pub health: u32,
+    /// Mana players start the match with, casting spells costs some.
+    #[serde(rename = "mn")]
+    pub mana: f32,
+    /// How long players can't be hurt again after taking damage.
+    #[serde(rename = "iv")]
+    pub invulnerable_seconds: f32,
+    /// How fast the bolts players cast fly, in world units per second.
+    #[serde(rename = "bs")]
+    pub bolt_speed: f32,
+    #[serde(rename = "a")]
+    pub arena: Arena,
+    /// Everything players can send, [`ClientMessage::SendEnemy`] and
+    /// [`ServerMessage::Update`] refer to them by index.
+    #[serde(rename = "en")]
+    pub enemies: Vec<EnemyType>,
+    #[serde(rename = "pk")]
+    pub pickups: Pickups,
+}
+
+impl MatchSettings {
+    /// Seconds per tick, the step everything is simulated with.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn tick(&self) -> f32 {
+        1. / self.tick_rate as f32
+    }
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        Self {
+            tick_rate: TICKRATE,
+            movement: Movement::default(),
+            health: PLAYER_HEALTH,
+            mana: PLAYER_MANA,
+            invulnerable_seconds: INVULNERABLE_SECONDS,
+            bolt_speed: BOLT_SPEED,
+            arena: Arena::default(),
+            enemies: Vec::new(),
+            pickups: Pickups::default(),
+        }
+    }
+}
 
 #[cfg(feature = "json")]
 pub fn serialize<T>(value: &T) -> anyhow::Result<Vec<u8>>
@@ -38,6 +140,16 @@ where
     Ok(bincode::deserialize(v)?)
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The server only understands binary messages.
+    #[serde(rename = "nb")]
+    NotBinary,
+    /// The message could not be decoded into a [`ClientMessage`].
+    #[serde(rename = "md")]
+    Malformed,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub enum ServerMessage {
     #[serde(rename = "w")]
@@ -45,15 +157,51 @@ pub enum ServerMessage {
         #[serde(rename = "i")]
         id: Uuid,
     },
+    #[serde(rename = "li")]
+    LoggedIn {
+        #[serde(rename = "t")]
+        token: String,
+    },
+    #[serde(rename = "lf")]
+    LoginFailed {
+        #[serde(rename = "r")]
+        reason: String,
+    },
+    #[serde(rename = "ar")]
+    AuthenticationRequired,
+    #[serde(rename = "e")]
+    Error {
+        #[serde(rename = "c")]
+        code: ErrorCode,
+    },
     #[serde(rename = "j")]
     PlayerJoined {
         #[serde(rename = "i")]
         id: Uuid,
         #[serde(rename = "n")]
         name: String,
+        #[serde(rename = "r")]
+        rating: u32,
     },
     #[serde(rename = "gb")]
     GoodBye(Uuid),
+    #[serde(rename = "an")]
+    Announcement {
+        #[serde(rename = "t")]
+        text: String,
+    },
+    /// The server stops accepting new matches and shuts down in `seconds`.
+    #[serde(rename = "sd")]
+    ShuttingDown {
+        #[serde(rename = "s")]
+        seconds: u64,
+    },
+    /// Sent right before the server closes the connection.
+    #[serde(rename = "d")]
+    Disconnected {
+        #[serde(rename = "r")]
+        reason: String,
+    },
     #[serde(rename = "ucn")]
     PlayerChangedName {
         #[serde(rename = "i")]
@@ -61,20 +209,75 @@ pub enum ServerMessage {
         #[serde(rename = "n")]
         new_name: String,
     },
+    #[serde(rename = "urc")]
+    PlayerRatingChanged {
+        #[serde(rename = "i")]
+        id: Uuid,
+        #[serde(rename = "r")]
+        rating: u32,
+    },
     #[serde(rename = "n")]
     NameNotAvailable {
         #[serde(rename = "n")]
         name: String,
     },
+    #[serde(rename = "nr")]
+    NameRejected {
+        #[serde(rename = "n")]
+        name: String,
+        #[serde(rename = "r")]
+        reason: String,
+    },
+    #[serde(rename = "ms")]
+    MatchStarted {
+        /// Tags the [`ClientMessage::State`] updates for this match.
+        #[serde(rename = "mi")]
+        match_id: Uuid,
+        #[serde(rename = "o")]
+        opponent: Uuid,
+        #[serde(rename = "sd")]
+        seed: u64,
+        #[serde(rename = "st")]
+        settings: MatchSettings,
+    },
+    /// Enemies the opponent sent, as indices into [`MatchSettings::enemies`].
     #[serde(rename = "u")]
     Update {
         #[serde(rename = "s")]
-        spawns: usize,
+        spawns: Vec<usize>,
     },
+    /// The match is over, the counts are from the point of view of the receiving player.
     #[serde(rename = "f")]
     Finish {
         #[serde(rename = "k")]
         enemy_kills: usize,
+        /// `None` if the match was aborted.
+        #[serde(rename = "w")]
+        winner: Option<Uuid>,
+        #[serde(rename = "es")]
+        enemies_sent: usize,
+        #[serde(rename = "er")]
+        enemies_received: usize,
+        /// How long the match took.
+        #[serde(rename = "s")]
+        seconds: u64,
+        /// How much the rating of the receiving player went up or down.
+        #[serde(rename = "rc")]
+        rating_change: i64,
+        /// How long both players have to agree on a rematch, 0 if there can't be one.
+        #[serde(rename = "rs")]
+        rematch_seconds: u64,
+    },
+    /// The opponent of the match that just finished wants a rematch, answer with
+    /// [`ClientMessage::Rematch`] or [`ClientMessage::BackToLobby`].
+    #[serde(rename = "ro")]
+    RematchOffered,
+    /// There won't be a rematch. Either it `expired` and both players are back in
+    /// the lobby, or the opponent left.
+    #[serde(rename = "rx")]
+    RematchCancelled {
+        #[serde(rename = "x")]
+        expired: bool,
     },
     #[serde(rename = "cr")]
     ChallengeReceived {
@@ -83,6 +286,8 @@ pub enum ServerMessage {
         #[serde(rename = "n")]
         name: String,
     },
+    /// The challenge is off, because it was denied or not answered in time, or because one
+    /// of the players can't play right now.
     #[serde(rename = "cd")]
     ChallengeDenied {
         #[serde(rename = "rid")]
@@ -93,14 +298,28 @@ pub enum ServerMessage {
         #[serde(rename = "rid")]
         request_id: Uuid,
     },
+    /// The answer to [`ClientMessage::Ping`].
+    #[serde(rename = "po")]
+    Pong,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub enum ClientMessage {
-    #[serde(rename = "c")]
-    Connect {
+    /// Logs into the account with the given name, registering it if it doesn't exist yet.
+    #[serde(rename = "l")]
+    Login {
         #[serde(rename = "n")]
         name: String,
+        #[serde(rename = "p")]
+        password: String,
+    },
+    /// Logs in again with a token from a previous [`ServerMessage::LoggedIn`].
+    #[serde(rename = "r")]
+    Resume {
+        #[serde(rename = "n")]
+        name: String,
+        #[serde(rename = "t")]
+        token: String,
     },
     #[serde(rename = "cn")]
     ChangeName {
@@ -122,9 +341,45 @@ pub enum ClientMessage {
         #[serde(rename = "rid")]
         request_id: Uuid,
     },
+    #[serde(rename = "jq")]
+    JoinQueue,
+    #[serde(rename = "lq")]
+    LeaveQueue,
+    /// Only counts for the match with the id from [`ServerMessage::MatchStarted`],
+    /// updates still on their way when the next match starts are dropped.
     #[serde(rename = "s")]
     State {
+        #[serde(rename = "mi")]
+        match_id: Uuid,
         #[serde(rename = "k")]
         kills: usize,
+        /// Ticks simulated since the match started, no more than have passed on the server.
+        #[serde(rename = "t")]
+        ticks: u32,
+        /// How far the player moved since the match started, not counting jumps across
+        /// wrapping edges. Checked against [`MatchSettings::movement`] for the ticks since
+        /// the last state, states from players moving further than it allows don't count.
+        #[serde(rename = "m")]
+        moved: glam::Vec2,
+    },
+    /// Spends kills on sending the enemy at this index of [`MatchSettings::enemies`]
+    /// to the opponent. Only kills already reported with [`ClientMessage::State`] count.
+    #[serde(rename = "se")]
+    SendEnemy {
+        #[serde(rename = "i")]
+        index: usize,
     },
+    /// The player ran out of health, which hands the win to the opponent.
+    #[serde(rename = "pd")]
+    Died,
+    /// Offers the opponent of the match that just finished to play again,
+    /// or accepts their [`ServerMessage::RematchOffered`].
+    #[serde(rename = "rm")]
+    Rematch,
+    /// Leaves the results of the match that just finished, turning down a rematch.
+    #[serde(rename = "bl")]
+    BackToLobby,
+    /// Asks for a [`ServerMessage::Pong`] to measure the round trip time.
+    #[serde(rename = "pi")]
+    Ping,
 }
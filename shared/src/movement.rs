@@ -0,0 +1,150 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+/// Leeway for rounding errors when checking movement.
+const TOLERANCE: f32 = 1.01;
+
+/// How players speed up and slow down, in world units per second.
+/// Clients step their own player with it every tick and the server checks how far they
+/// report having moved against it.
+/// The long names are for config files.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Movement {
+    #[serde(rename = "m", alias = "max_speed")]
+    pub max_speed: f32,
+    /// How quickly the velocity turns towards where the player wants to go.
+    #[serde(rename = "a", alias = "acceleration")]
+    pub acceleration: f32,
+    /// How quickly the velocity drops to 0 without any input.
+    #[serde(rename = "f", alias = "friction")]
+    pub friction: f32,
+}
+
+impl Default for Movement {
+    fn default() -> Self {
+        Self {
+            max_speed: 60.,
+            acceleration: 600.,
+            friction: 480.,
+        }
+    }
+}
+
+impl Movement {
+    /// The velocity after `dt` seconds of `input`, which is a direction no longer than 1.
+    /// Shorter inputs, like a stick that's only tilted a bit, aim for a lower speed.
+    #[must_use]
+    pub fn step(&self, velocity: Vec2, input: Vec2, dt: f32) -> Vec2 {
+        let input = if input.length_squared() > 1. {
+            input.normalize()
+        } else {
+            input
+        };
+        let target = input * self.max_speed;
+        let rate = if input == Vec2::ZERO {
+            self.friction
+        } else {
+            self.acceleration
+        };
+        let difference = target - velocity;
+        let change = rate * dt;
+        if difference.length() <= change {
+            target
+        } else {
+            velocity + difference.normalize() * change
+        }
+    }
+
+    /// Whether stepping `ticks` times by `dt` seconds could have moved a player by `moved`,
+    /// with the max speed raised by up to `boost` like a speed pickup does. Stepping never
+    /// goes faster than the max speed or the velocity it started from, and walls only take
+    /// speed away, so no tick gets further than one at the max speed.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn allows(&self, moved: Vec2, ticks: u32, dt: f32, boost: f32) -> bool {
+        moved.length() <= self.max_speed * boost.max(1.) * dt * ticks as f32 * TOLERANCE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f32 = 1. / 64.;
+
+    /// Steps with the same input until the velocity doesn't change anymore.
+    fn settle(movement: &Movement, input: Vec2) -> Vec2 {
+        let mut velocity = Vec2::ZERO;
+        for _ in 0..1000 {
+            velocity = movement.step(velocity, input, TICK);
+        }
+        velocity
+    }
+
+    #[test]
+    fn diagonals_are_as_fast_as_straight_lines() {
+        let movement = Movement::default();
+        let straight = settle(&movement, Vec2::new(1., 0.));
+        let diagonal = settle(&movement, Vec2::new(1., 1.));
+        assert!((straight.length() - movement.max_speed).abs() < 1e-3);
+        assert!((diagonal.length() - movement.max_speed).abs() < 1e-3);
+    }
+
+    #[test]
+    fn short_inputs_aim_for_lower_speeds() {
+        let movement = Movement::default();
+        let velocity = settle(&movement, Vec2::new(0.5, 0.));
+        assert!((velocity.length() - movement.max_speed / 2.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn speed_changes_by_the_acceleration_at_most() {
+        let movement = Movement::default();
+        let velocity = movement.step(Vec2::ZERO, Vec2::new(0., 1.), TICK);
+        assert!((velocity.length() - movement.acceleration * TICK).abs() < 1e-3);
+    }
+
+    #[test]
+    fn friction_stops_players_without_input() {
+        let movement = Movement::default();
+        let start = Vec2::new(movement.max_speed, 0.);
+        let slowed = movement.step(start, Vec2::ZERO, TICK);
+        assert!((start.length() - slowed.length() - movement.friction * TICK).abs() < 1e-3);
+        let mut velocity = start;
+        for _ in 0..64 {
+            velocity = movement.step(velocity, Vec2::ZERO, TICK);
+        }
+        assert_eq!(velocity, Vec2::ZERO);
+    }
+
+    #[test]
+    fn stepping_stays_within_what_is_allowed() {
+        let movement = Movement::default();
+        let boosted = Movement {
+            max_speed: movement.max_speed * 1.5,
+            ..movement
+        };
+        let mut velocity = settle(&boosted, Vec2::new(-1., 1.));
+        let mut moved = Vec2::ZERO;
+        for _ in 0..64 {
+            velocity = boosted.step(velocity, Vec2::new(-1., 1.), TICK);
+            moved += velocity * TICK;
+        }
+        assert!(movement.allows(moved, 64, TICK, 1.5));
+        assert!(!movement.allows(moved, 64, TICK, 1.));
+        assert!(!movement.allows(moved, 63, TICK, 1.5));
+        // the boost ran out
+        for ticks in 65..=128 {
+            velocity = movement.step(velocity, Vec2::new(-1., 1.), TICK);
+            moved += velocity * TICK;
+            assert!(movement.allows(moved, ticks, TICK, 1.5));
+        }
+        let mut slowed = Vec2::ZERO;
+        for _ in 0..64 {
+            velocity = movement.step(velocity, Vec2::new(-1., 1.), TICK);
+            slowed += velocity * TICK;
+        }
+        assert!(movement.allows(slowed, 64, TICK, 1.));
+    }
+}
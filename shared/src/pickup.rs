@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+/// What a killed enemy can leave behind.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickupKind {
+    Health,
+    Mana,
+    /// Makes bolts hurt more for a while.
+    Damage,
+    /// Makes the player faster for a while.
+    Speed,
+}
+
+impl PickupKind {
+    pub const ALL: [Self; 4] = [Self::Health, Self::Mana, Self::Damage, Self::Speed];
+}
+
+/// How often enemies drop pickups and how strong they are, every kind is as likely.
+/// The long names are for config files.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Pickups {
+    /// Between 0 and 1.
+    #[serde(rename = "c", alias = "drop_chance")]
+    pub drop_chance: f32,
+    /// Health a health pickup restores.
+    #[serde(rename = "h", alias = "heal")]
+    pub heal: u32,
+    /// Mana a mana pickup restores.
+    #[serde(rename = "m", alias = "mana")]
+    pub mana: f32,
+    /// How long damage and speed buffs last.
+    #[serde(rename = "s", alias = "buff_seconds")]
+    pub buff_seconds: f32,
+    /// Added to the damage of bolts during a damage buff.
+    #[serde(rename = "d", alias = "damage_bonus")]
+    pub damage_bonus: u32,
+    /// The max speed is multiplied by this during a speed buff.
+    #[serde(rename = "sm", alias = "speed_multiplier")]
+    pub speed_multiplier: f32,
+}
+
+impl Default for Pickups {
+    fn default() -> Self {
+        Self {
+            drop_chance: 0.25,
+            heal: 3,
+            mana: 50.,
+            buff_seconds: 10.,
+            damage_bonus: 1,
+            speed_multiplier: 1.5,
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::map::TileMap;
+use glam::Vec2;
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+/// Column and row of a tile.
+pub type Tile = (usize, usize);
+
+/// Costs of a step to a neighbouring tile, scaled up so paths don't depend on float rounding.
+const STRAIGHT: u32 = 10;
+const DIAGONAL: u32 = 14;
+
+/// Straight towards the target, as a direction no longer than 1.
+pub fn seek(from: Vec2, target: Vec2) -> Vec2 {
+    (target - from).normalize_or_zero()
+}
+
+/// Straight away from the threat.
+pub fn flee(from: Vec2, threat: Vec2) -> Vec2 {
+    -seek(from, threat)
+}
+
+/// Pushes away from neighbours closer than `radius`, the closer they are the harder.
+pub fn separation(from: Vec2, neighbours: impl Iterator<Item = Vec2>, radius: f32) -> Vec2 {
+    neighbours
+        .map(|neighbour| from - neighbour)
+        .filter(|offset| *offset != Vec2::ZERO && offset.length() < radius)
+        .map(|offset| {
+            let distance = offset.length();
+            offset / distance * (1. - distance / radius)
+        })
+        .fold(Vec2::ZERO, |push, offset| push + offset)
+}
+
+#[derive(PartialEq, Eq)]
+struct Open {
+    /// Cost so far plus the estimate of the rest.
+    estimate: u32,
+    tile: Tile,
+}
+
+impl Ord for Open {
+    /// The heap pops the cheapest tile first, ties are broken by position to stay deterministic.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .cmp(&self.estimate)
+            .then_with(|| other.tile.cmp(&self.tile))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The cheapest cost between two tiles if nothing is in the way.
+fn octile_distance(a: Tile, b: Tile) -> u32 {
+    let dx = u32::try_from(a.0.abs_diff(b.0)).unwrap_or(u32::MAX / 2);
+    let dy = u32::try_from(a.1.abs_diff(b.1)).unwrap_or(u32::MAX / 2);
+    STRAIGHT * dx.max(dy) + (DIAGONAL - STRAIGHT) * dx.min(dy)
+}
+
+/// The open tiles around the tile and the cost of stepping there. Diagonal steps
+/// need both tiles beside them to be open, so nothing cuts through a wall's corner.
+fn neighbours(map: &TileMap, (column, row): Tile) -> impl Iterator<Item = (Tile, u32)> + '_ {
+    let open = move |column: usize, row: usize| {
+        column < map.columns() && row < map.rows() && !map.is_solid(column, row)
+    };
+    [
+        (-1, 0),
+        (1, 0),
+        (0, -1),
+        (0, 1),
+        (-1, -1),
+        (1, -1),
+        (-1, 1),
+        (1, 1),
+    ]
+    .into_iter()
+    .filter_map(move |(dx, dy): (isize, isize)| {
+        let next_column = column.checked_add_signed(dx)?;
+        let next_row = row.checked_add_signed(dy)?;
+        if !open(next_column, next_row) {
+            return None;
+        }
+        if dx != 0 && dy != 0 {
+            (open(next_column, row) && open(column, next_row))
+                .then_some(((next_column, next_row), DIAGONAL))
+        } else {
+            Some(((next_column, next_row), STRAIGHT))
+        }
+    })
+}
+
+/// The shortest way from `start` to `goal` with A*, as the tiles to walk through
+/// after `start`, up to and including `goal`. `None` if walls are in the way.
+pub fn find_path(map: &TileMap, start: Tile, goal: Tile) -> Option<Vec<Tile>> {
+    let mut open = BinaryHeap::from([Open {
+        estimate: octile_distance(start, goal),
+        tile: start,
+    }]);
+    let mut costs = HashMap::from([(start, 0)]);
+    let mut came_from: HashMap<Tile, Tile> = HashMap::new();
+    while let Some(Open { tile, .. }) = open.pop() {
+        if tile == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(previous) = came_from.get(&current) {
+                if *previous == start {
+                    break;
+                }
+                path.push(*previous);
+                current = *previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        let cost = costs[&tile];
+        for (next, step) in neighbours(map, tile) {
+            let next_cost = cost + step;
+            if costs.get(&next).is_none_or(|known| next_cost < *known) {
+                costs.insert(next, next_cost);
+                came_from.insert(next, tile);
+                open.push(Open {
+                    estimate: next_cost + octile_distance(next, goal),
+                    tile: next,
+                });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map from rows of `.` for open and `#` for solid tiles, spawning on column 1, row 1.
+    fn map(tiles: &str) -> TileMap {
+        TileMap::parse(&format!(
+            r##"
+tile_size = 16
+spawn = [1, 1]
+tiles = """
+{tiles}
+"""
+[legend."."]
+color = [0, 0, 0]
+[legend."#"]
+solid = true
+color = [255, 255, 255]
+"##
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn walks_around_walls() {
+        let map = map("#####\n#.#.#\n#.#.#\n#...#\n#####");
+        assert_eq!(
+            find_path(&map, (1, 1), (3, 1)),
+            Some(vec![(1, 2), (1, 3), (2, 3), (3, 3), (3, 2), (3, 1)])
+        );
+    }
+
+    #[test]
+    fn takes_diagonals_in_the_open() {
+        let map = map("....\n....\n....\n....");
+        assert_eq!(
+            find_path(&map, (0, 0), (3, 3)),
+            Some(vec![(1, 1), (2, 2), (3, 3)])
+        );
+    }
+
+    #[test]
+    fn does_not_cut_corners() {
+        let map = map("..\n#.");
+        assert_eq!(find_path(&map, (0, 0), (1, 1)), Some(vec![(1, 0), (1, 1)]));
+    }
+
+    #[test]
+    fn no_path_through_walls() {
+        let map = map("#####\n#.#.#\n#####");
+        assert_eq!(find_path(&map, (1, 1), (3, 1)), None);
+    }
+
+    #[test]
+    fn octile_distance_matches_step_costs() {
+        assert_eq!(octile_distance((0, 0), (3, 1)), 2 * STRAIGHT + DIAGONAL);
+        assert_eq!(octile_distance((2, 5), (2, 5)), 0);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::Direction;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AnimationKind {
+    Idle,
+    Walk,
+    Cast,
+    Hurt,
+    Die,
+}
+
+/// A sequence of sprite sheet frames, one sequence per direction.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Clip {
+    /// Frames are indices into the sprite sheet, counted left to right, top to bottom.
+    frames: HashMap<Direction, Vec<usize>>,
+    /// Seconds each frame is shown.
+    frame_duration: f32,
+    /// Overrides `frame_duration` for the first frames.
+    #[serde(default)]
+    durations: Vec<f32>,
+    /// Clips that don't loop stop on their last frame.
+    #[serde(default)]
+    looping: bool,
+}
+
+/// Diagonals fall back to the horizontal direction, anything else to facing down.
+fn horizontal(direction: Direction) -> Direction {
+    match direction {
+        Direction::UpRight | Direction::DownRight => Direction::Right,
+        Direction::UpLeft | Direction::DownLeft => Direction::Left,
+        _ => Direction::Down,
+    }
+}
+
+impl Clip {
+    fn frames(&self, direction: Direction) -> &[usize] {
+        self.frames
+            .get(&direction)
+            .or_else(|| self.frames.get(&horizontal(direction)))
+            .or_else(|| self.frames.get(&Direction::Down))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    fn duration(&self, index: usize) -> f32 {
+        self.durations
+            .get(index)
+            .copied()
+            .unwrap_or(self.frame_duration)
+    }
+
+    fn length(&self, direction: Direction) -> f32 {
+        (0..self.frames(direction).len())
+            .map(|index| self.duration(index))
+            .sum()
+    }
+
+    fn frame_at(&self, direction: Direction, elapsed: f32) -> usize {
+        let frames = self.frames(direction);
+        let length = self.length(direction);
+        if length <= 0. {
+            return frames.first().copied().unwrap_or_default();
+        }
+        let mut time = if self.looping {
+            elapsed % length
+        } else {
+            elapsed.min(length)
+        };
+        for (index, frame) in frames.iter().enumerate() {
+            time -= self.duration(index);
+            if time < 0. {
+                return *frame;
+            }
+        }
+        frames.last().copied().unwrap_or_default()
+    }
+}
+
+/// All clips of a sprite sheet, loaded from a toml file keyed by [`AnimationKind`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(transparent)]
+pub struct Animations {
+    clips: HashMap<AnimationKind, Clip>,
+}
+
+impl Animations {
+    /// Missing clips fall back to idle.
+    fn clip(&self, kind: AnimationKind) -> Option<&Clip> {
+        self.clips
+            .get(&kind)
+            .or_else(|| self.clips.get(&AnimationKind::Idle))
+    }
+}
+
+/// Which clip a character is playing and since when, the clips themselves are shared.
+#[derive(Clone, Copy, Debug)]
+pub struct Animator {
+    kind: AnimationKind,
+    direction: Direction,
+    started: f64,
+}
+
+impl Default for Animator {
+    fn default() -> Self {
+        Self {
+            kind: AnimationKind::Idle,
+            direction: Direction::Down,
+            started: 0.,
+        }
+    }
+}
+
+impl Animator {
+    pub fn kind(&self) -> AnimationKind {
+        self.kind
+    }
+
+    /// Switches to the clip, restarting only if it isn't already playing.
+    pub fn play(&mut self, kind: AnimationKind, now: f64) {
+        if self.kind != kind {
+            self.kind = kind;
+            self.started = now;
+        }
+    }
+
+    /// Turning keeps the clip running, so walk cycles don't stutter.
+    pub fn face(&mut self, direction: Direction) {
+        self.direction = direction;
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn elapsed(&self, now: f64) -> f32 {
+        (now - self.started) as f32
+    }
+
+    /// Whether a clip that doesn't loop reached its last frame.
+    pub fn is_finished(&self, animations: &Animations, now: f64) -> bool {
+        animations
+            .clip(self.kind)
+            .is_none_or(|clip| !clip.looping && self.elapsed(now) >= clip.length(self.direction))
+    }
+
+    /// The sprite sheet frame to draw.
+    pub fn frame(&self, animations: &Animations, now: f64) -> usize {
+        animations
+            .clip(self.kind)
+            .map_or(0, |clip| clip.frame_at(self.direction, self.elapsed(now)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(looping: bool) -> Clip {
+        Clip {
+            frames: HashMap::from([
+                (Direction::Down, vec![0, 1, 2]),
+                (Direction::Right, vec![3, 4, 5]),
+            ]),
+            frame_duration: 0.1,
+            durations: vec![0.3],
+            looping,
+        }
+    }
+
+    #[test]
+    fn frames_follow_their_durations() {
+        let clip = clip(true);
+        assert_eq!(clip.frame_at(Direction::Down, 0.), 0);
+        assert_eq!(clip.frame_at(Direction::Down, 0.25), 0);
+        assert_eq!(clip.frame_at(Direction::Down, 0.35), 1);
+        assert_eq!(clip.frame_at(Direction::Down, 0.45), 2);
+    }
+
+    #[test]
+    fn looping_clips_start_over() {
+        assert_eq!(clip(true).frame_at(Direction::Down, 0.55), 0);
+        assert_eq!(clip(true).frame_at(Direction::Down, 0.85), 1);
+    }
+
+    #[test]
+    fn other_clips_stop_on_their_last_frame() {
+        assert_eq!(clip(false).frame_at(Direction::Down, 0.55), 2);
+        assert_eq!(clip(false).frame_at(Direction::Down, 10.), 2);
+    }
+
+    #[test]
+    fn missing_directions_fall_back() {
+        let clip = clip(true);
+        assert_eq!(clip.frame_at(Direction::DownRight, 0.), 3);
+        assert_eq!(clip.frame_at(Direction::UpLeft, 0.), 0);
+        assert_eq!(clip.frame_at(Direction::Up, 0.35), 1);
+    }
+}
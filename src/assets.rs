@@ -0,0 +1,207 @@
+use crate::{animation::Animations, map::TileMap};
+use anyhow::{anyhow, Context};
+use macroquad::{
+    audio::{load_sound_from_bytes, Sound},
+    file::load_file,
+    prelude::{Rect, Texture2D},
+    text::load_ttf_font_from_bytes,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+
+/// Copies of the default assets built into the client, keyed by their path in the manifest.
+const EMBEDDED: &[(&str, &[u8])] = &[
+    ("manifest.toml", include_bytes!("../assets/manifest.toml")),
+    (
+        "8Bit Wizard.png",
+        include_bytes!("../assets/8Bit Wizard.png"),
+    ),
+    (
+        "wizard.animations.toml",
+        include_bytes!("../assets/wizard.animations.toml"),
+    ),
+    ("maps/open.toml", include_bytes!("../assets/maps/open.toml")),
+    (
+        "maps/pillars.toml",
+        include_bytes!("../assets/maps/pillars.toml"),
+    ),
+    (
+        "maps/crossroads.toml",
+        include_bytes!("../assets/maps/crossroads.toml"),
+    ),
+];
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    textures: HashMap<String, String>,
+    #[serde(default)]
+    sprite_sheets: HashMap<String, SpriteSheetEntry>,
+    #[serde(default)]
+    sounds: HashMap<String, String>,
+    #[serde(default)]
+    fonts: HashMap<String, String>,
+    #[serde(default)]
+    maps: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct SpriteSheetEntry {
+    texture: String,
+    frame_width: f32,
+    frame_height: f32,
+    animations: String,
+}
+
+/// A texture cut into a grid of equally sized frames.
+#[derive(Clone)]
+pub struct SpriteSheet {
+    pub texture: Texture2D,
+    pub frame_width: f32,
+    pub frame_height: f32,
+    pub animations: Animations,
+}
+
+impl SpriteSheet {
+    /// The part of the texture showing the frame, counted left to right, top to bottom.
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss,
+        clippy::cast_possible_truncation
+    )]
+    pub fn source(&self, frame: usize) -> Rect {
+        let cols = ((self.texture.width() / self.frame_width).floor() as usize).max(1);
+        Rect::new(
+            (frame % cols) as f32 * self.frame_width,
+            (frame / cols) as f32 * self.frame_height,
+            self.frame_width,
+            self.frame_height,
+        )
+    }
+}
+
+pub struct Assets {
+    sprite_sheets: HashMap<String, SpriteSheet>,
+    sounds: HashMap<String, Sound>,
+    fonts: HashMap<String, Vec<u8>>,
+    maps: HashMap<String, TileMap>,
+}
+
+/// Loads the file from the assets directory, falling back to the embedded copy.
+async fn load(directory: &Path, file: &str) -> anyhow::Result<Vec<u8>> {
+    let path = directory.join(file);
+    match load_file(&path.to_string_lossy()).await {
+        Ok(bytes) => Ok(bytes),
+        Err(err) => {
+            let (_, embedded) = EMBEDDED
+                .iter()
+                .find(|(name, _)| *name == file)
+                .ok_or_else(|| anyhow!("Asset {} is missing: {}", path.display(), err))?;
+            log::warn!("Using the built in copy of {}: {}", file, err);
+            Ok(embedded.to_vec())
+        }
+    }
+}
+
+async fn load_string(directory: &Path, file: &str) -> anyhow::Result<String> {
+    String::from_utf8(load(directory, file).await?)
+        .with_context(|| format!("Asset {file} is not valid utf-8"))
+}
+
+impl Assets {
+    /// Loads everything listed in the `manifest.toml` of the directory.
+    pub async fn load(directory: &Path) -> anyhow::Result<Self> {
+        let manifest: Manifest = toml::from_str(&load_string(directory, "manifest.toml").await?)
+            .context("Invalid asset manifest")?;
+
+        let mut textures = HashMap::new();
+        for (name, file) in &manifest.textures {
+            let bytes = load(directory, file).await?;
+            textures.insert(
+                name.as_str(),
+                Texture2D::from_file_with_format(&bytes, None),
+            );
+        }
+
+        let mut sprite_sheets = HashMap::new();
+        for (name, entry) in manifest.sprite_sheets {
+            let texture = *textures.get(entry.texture.as_str()).ok_or_else(|| {
+                anyhow!(
+                    "Sprite sheet {} uses the unknown texture {}",
+                    name,
+                    entry.texture
+                )
+            })?;
+            let animations = toml::from_str(&load_string(directory, &entry.animations).await?)
+                .with_context(|| format!("Invalid animations in {}", entry.animations))?;
+            sprite_sheets.insert(
+                name,
+                SpriteSheet {
+                    texture,
+                    frame_width: entry.frame_width,
+                    frame_height: entry.frame_height,
+                    animations,
+                },
+            );
+        }
+
+        let mut sounds = HashMap::new();
+        for (name, file) in manifest.sounds {
+            let sound = load_sound_from_bytes(&load(directory, &file).await?)
+                .await
+                .with_context(|| format!("Invalid sound {file}"))?;
+            sounds.insert(name, sound);
+        }
+
+        let mut fonts = HashMap::new();
+        for (name, file) in manifest.fonts {
+            let bytes = load(directory, &file).await?;
+            load_ttf_font_from_bytes(&bytes)
+                .map_err(|err| anyhow!("Invalid font {}: {}", file, err))?;
+            fonts.insert(name, bytes);
+        }
+
+        let mut maps = HashMap::new();
+        for (name, file) in manifest.maps {
+            let map = TileMap::parse(&load_string(directory, &file).await?)
+                .with_context(|| format!("Invalid map {file}"))?;
+            maps.insert(name, map);
+        }
+
+        Ok(Self {
+            sprite_sheets,
+            sounds,
+            fonts,
+            maps,
+        })
+    }
+
+    pub fn sprite_sheet(&self, name: &str) -> Option<&SpriteSheet> {
+        self.sprite_sheets.get(name)
+    }
+
+    pub fn map(&self, name: &str) -> Option<&TileMap> {
+        self.maps.get(name)
+    }
+
+    pub fn sound(&self, name: &str) -> Option<Sound> {
+        self.sounds.get(name).copied()
+    }
+
+    /// Makes the `ui` font, if there is one, the default font of egui.
+    pub fn install_fonts(&self) {
+        let Some(font) = self.fonts.get("ui") else {
+            return;
+        };
+        let mut definitions = egui::FontDefinitions::default();
+        definitions
+            .font_data
+            .insert("ui".to_owned(), egui::FontData::from_owned(font.clone()));
+        definitions
+            .families
+            .entry(egui::FontFamily::Proportional)
+            .or_default()
+            .insert(0, "ui".to_owned());
+        egui_macroquad::cfg(|egui_ctx| egui_ctx.set_fonts(definitions));
+    }
+}
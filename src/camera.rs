@@ -0,0 +1,63 @@
+use glam::Vec2;
+use macroquad::prelude::{screen_height, screen_width, Camera2D, Rect};
+use shared::Edges;
+
+/// Shows the whole world as large as the window allows, with black bars on the remaining sides.
+pub struct WorldCamera {
+    pub camera: Camera2D,
+    /// Top left corner of the world on the screen.
+    origin: Vec2,
+    /// Screen pixels per world unit.
+    scale: f32,
+}
+
+impl WorldCamera {
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(world: Vec2) -> Self {
+        let screen = Vec2::new(screen_width(), screen_height());
+        let scale = (screen.x / world.x).min(screen.y / world.y);
+        let size = world * scale;
+        let origin = (screen - size) / 2.;
+        let mut camera = Camera2D::from_display_rect(Rect::new(0., 0., world.x, world.y));
+        // the bars are the same size on both sides, so it doesn't matter that
+        // the viewport counts from the bottom of the screen instead of the top
+        camera.viewport = Some((
+            origin.x.round() as i32,
+            origin.y.round() as i32,
+            size.x.round() as i32,
+            size.y.round() as i32,
+        ));
+        Self {
+            camera,
+            origin,
+            scale,
+        }
+    }
+
+    /// Converts a position in the window, like the mouse cursor, into world units.
+    pub fn screen_to_world(&self, point: Vec2) -> Vec2 {
+        (point - self.origin) / self.scale
+    }
+}
+
+/// Keeps something of the given size inside the world, `position` being its top left corner.
+pub fn confine(edges: Edges, world: Vec2, position: &mut Vec2, size: Vec2) {
+    match edges {
+        Edges::Wrap => {
+            if position.x > world.x {
+                position.x = -size.x;
+            } else if position.x < -size.x {
+                position.x = world.x;
+            }
+            if position.y > world.y {
+                position.y = -size.y;
+            } else if position.y < -size.y {
+                position.y = world.y;
+            }
+        }
+        Edges::Clamp => {
+            position.x = position.x.clamp(0., (world.x - size.x).max(0.));
+            position.y = position.y.clamp(0., (world.y - size.y).max(0.));
+        }
+    }
+}
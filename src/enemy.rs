@@ -0,0 +1,160 @@
+use crate::{
+    ai::{self, Tile},
+    animation::{AnimationKind, Animator},
+    camera::confine,
+    map::TileMap,
+    Direction,
+};
+use glam::Vec2;
+use shared::{Behaviour, EnemyType, MatchSettings, Movement, Rng};
+
+/// Wandering enemies keep their direction for a random time between these, in seconds.
+const WANDER_SECONDS: (f32, f32) = (1., 3.);
+
+/// Chasing enemies look for a new path this often, in ticks.
+const REPATH_TICKS: u32 = 16;
+
+/// Kiting enemies back off when the player is closer than this, in world units.
+const KITE_DISTANCE: f32 = 64.;
+
+/// Enemies closer than this push each other apart, in world units.
+pub const SEPARATION_RADIUS: f32 = 16.;
+
+/// How much pushing apart counts compared to where an enemy wants to go.
+const SEPARATION_WEIGHT: f32 = 1.5;
+
+/// An enemy the opponent sent.
+pub struct Enemy {
+    /// Index into [`MatchSettings::enemies`].
+    pub kind: usize,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub health: u32,
+    pub animation: Animator,
+    pub size: Vec2,
+    /// The tiles left on the way to the player, the next one last.
+    path: Vec<Tile>,
+    /// The direction a wandering enemy walks in.
+    heading: Vec2,
+    /// Ticks until the path or the heading is picked again.
+    rethink_in: u32,
+}
+
+impl Enemy {
+    pub fn new(kind: usize, enemy_type: &EnemyType, position: Vec2, size: Vec2) -> Self {
+        Self {
+            kind,
+            position,
+            velocity: Vec2::ZERO,
+            health: enemy_type.health,
+            animation: Animator::default(),
+            size,
+            path: Vec::new(),
+            heading: Vec2::ZERO,
+            rethink_in: 0,
+        }
+    }
+
+    pub fn center(&self) -> Vec2 {
+        self.position + self.size / 2.
+    }
+
+    /// Whether the enemy overlaps the box, `position` being its top left corner.
+    pub fn touches(&self, position: Vec2, size: Vec2) -> bool {
+        self.position.x < position.x + size.x
+            && position.x < self.position.x + self.size.x
+            && self.position.y < position.y + size.y
+            && position.y < self.position.y + self.size.y
+    }
+
+    /// Moves the enemy by one tick, the same way players move. `player` is the center
+    /// of the player and `separation` the push away from the other enemies.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn step(
+        &mut self,
+        settings: &MatchSettings,
+        map: &TileMap,
+        player: Vec2,
+        separation: Vec2,
+        rng: &mut Rng,
+        now: f64,
+    ) {
+        let Some(enemy_type) = settings.enemies.get(self.kind) else {
+            return;
+        };
+        let center = self.center();
+        let goal = match enemy_type.behaviour {
+            Behaviour::Chase => self.follow(map, center, player),
+            Behaviour::Wander => {
+                if self.rethink_in == 0 {
+                    let angle = rng.range(0., std::f32::consts::TAU);
+                    self.heading = Vec2::new(angle.cos(), angle.sin());
+                    let seconds = rng.range(WANDER_SECONDS.0, WANDER_SECONDS.1);
+                    self.rethink_in = (seconds / settings.tick()) as u32;
+                }
+                self.heading
+            }
+            Behaviour::Kite => {
+                if center.distance(player) < KITE_DISTANCE {
+                    ai::flee(center, player)
+                } else {
+                    self.follow(map, center, player)
+                }
+            }
+        };
+        self.rethink_in = self.rethink_in.saturating_sub(1);
+        let input = goal + separation * SEPARATION_WEIGHT;
+
+        let movement = Movement {
+            max_speed: enemy_type.speed,
+            ..settings.movement
+        };
+        let tick = settings.tick();
+        let velocity = movement.step(self.velocity, input, tick);
+        let moved = map.move_and_collide(self.position, self.size, velocity * tick);
+        self.velocity = (moved - self.position) / tick;
+        if self.velocity == Vec2::ZERO {
+            // stuck in a corner, think again right away
+            self.rethink_in = 0;
+        }
+        self.position = moved;
+        confine(
+            settings.arena.edges,
+            map.size(),
+            &mut self.position,
+            self.size,
+        );
+
+        if let Some(direction) = Direction::from_vector(self.velocity) {
+            self.animation.face(direction);
+        }
+        let kind = if self.velocity == Vec2::ZERO {
+            AnimationKind::Idle
+        } else {
+            AnimationKind::Walk
+        };
+        self.animation.play(kind, now);
+    }
+
+    /// Heads for the next tile on the way to the target, or straight at it on the last one.
+    fn follow(&mut self, map: &TileMap, center: Vec2, target: Vec2) -> Vec2 {
+        if self.rethink_in == 0 {
+            self.rethink_in = REPATH_TICKS;
+            self.path = match (map.tile_at(center), map.tile_at(target)) {
+                (Some(start), Some(goal)) => ai::find_path(map, start, goal).unwrap_or_default(),
+                _ => Vec::new(),
+            };
+            self.path.reverse();
+        }
+        while let Some(next) = self.path.last() {
+            if map.tile_center(*next).distance(center) > 1. {
+                break;
+            }
+            self.path.pop();
+        }
+        match self.path.last() {
+            Some(next) if self.path.len() > 1 => ai::seek(center, map.tile_center(*next)),
+            _ => ai::seek(center, target),
+        }
+    }
+}
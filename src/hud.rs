@@ -0,0 +1,147 @@
+use crate::{input::Action, pickup, projectile, Game};
+use egui::{Align2, Color32, Context, ProgressBar, RichText};
+use macroquad::prelude::{get_fps, get_time, Color};
+use shared::PickupKind;
+
+const HEALTH_COLOR: Color32 = Color32::from_rgb(200, 50, 50);
+const MANA_COLOR: Color32 = Color32::from_rgb(50, 100, 220);
+const MARGIN: egui::Vec2 = egui::Vec2::new(8., 8.);
+const BAR_WIDTH: f32 = 160.;
+
+fn color32(color: Color) -> Color32 {
+    let [r, g, b, a] = color.into();
+    Color32::from_rgba_unmultiplied(r, g, b, a)
+}
+
+/// `12:34` for a duration in seconds.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn clock(seconds: f64) -> String {
+    let seconds = seconds.max(0.) as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// The player's own state in the top left corner.
+#[allow(clippy::cast_precision_loss)]
+pub fn player(ctx: &Context, game: &Game) {
+    let state = &game.player_state;
+    let now = get_time();
+    egui::Area::new("hud_player")
+        .anchor(Align2::LEFT_TOP, MARGIN)
+        .interactable(false)
+        .show(ctx, |ui| {
+            let health = state.health as f32 / game.settings.health.max(1) as f32;
+            ui.add(
+                ProgressBar::new(health)
+                    .desired_width(BAR_WIDTH)
+                    .fill(HEALTH_COLOR)
+                    .text(format!("Health {}/{}", state.health, game.settings.health)),
+            );
+            let mana = if game.settings.mana > 0. {
+                state.mana / game.settings.mana
+            } else {
+                0.
+            };
+            ui.add(
+                ProgressBar::new(mana)
+                    .desired_width(BAR_WIDTH)
+                    .fill(MANA_COLOR)
+                    .text(format!("Mana {:.0}/{:.0}", state.mana, game.settings.mana)),
+            );
+
+            #[allow(clippy::cast_possible_truncation)]
+            let recharged = (1. - (state.cast_ready_at - now) / projectile::COOLDOWN_SECONDS)
+                .clamp(0., 1.) as f32;
+            let bolt = if state.mana < projectile::MANA_COST {
+                "no mana"
+            } else if recharged < 1. {
+                "recharging"
+            } else {
+                "ready"
+            };
+            ui.add(
+                ProgressBar::new(recharged)
+                    .desired_width(BAR_WIDTH)
+                    .text(format!(
+                        "Bolt [{}] {}",
+                        game.input.describe(Action::Cast),
+                        bolt
+                    )),
+            );
+
+            for (kind, name, until) in [
+                (PickupKind::Damage, "Damage", state.damage_buff_until),
+                (PickupKind::Speed, "Speed", state.speed_buff_until),
+            ] {
+                if now < until {
+                    ui.label(
+                        RichText::new(format!("{} buff {:.0}s", name, until - now))
+                            .color(color32(pickup::color(kind))),
+                    );
+                }
+            }
+
+            let unspent = game.unspent_kills();
+            ui.label(format!("Kills {} ({} to spend)", state.kills, unspent));
+            if game.opponent.is_some() {
+                let actions = [Action::Send1, Action::Send2, Action::Send3, Action::Send4];
+                for (enemy, action) in game.settings.enemies.iter().zip(actions) {
+                    let ready = unspent / enemy.cost.max(1);
+                    let text = RichText::new(format!(
+                        "[{}] {} x{}",
+                        game.input.describe(action),
+                        enemy.name,
+                        ready
+                    ));
+                    ui.label(if ready == 0 { text.weak() } else { text });
+                }
+            }
+        });
+}
+
+/// The opponent and the match timer in the top right corner.
+pub fn opponent(ctx: &Context, game: &Game) {
+    egui::Area::new("hud_opponent")
+        .anchor(Align2::RIGHT_TOP, MARGIN * egui::Vec2::new(-1., 1.))
+        .interactable(false)
+        .show(ctx, |ui| {
+            ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+                let Some(id) = game.opponent else {
+                    ui.label(if game.queued {
+                        "Searching for a match"
+                    } else {
+                        "Not in a match"
+                    });
+                    return;
+                };
+                match game.players.get(&id) {
+                    Some(opponent) => {
+                        ui.label(format!("vs {} ({})", opponent.name, opponent.rating));
+                    }
+                    None => {
+                        ui.label("Opponent left");
+                    }
+                }
+                ui.heading(clock(get_time() - game.match_started_at));
+            });
+        });
+}
+
+/// Frame rate, ping and entity counts in the bottom left corner.
+pub fn debug(ctx: &Context, game: &Game) {
+    egui::Area::new("hud_debug")
+        .anchor(Align2::LEFT_BOTTOM, MARGIN * egui::Vec2::new(1., -1.))
+        .interactable(false)
+        .show(ctx, |ui| {
+            ui.label(format!("FPS {}", get_fps()));
+            match game.ping {
+                Some(ping) => ui.label(format!("Ping {:.0} ms", ping * 1000.)),
+                None => ui.label("Ping -"),
+            };
+            ui.label(format!(
+                "Enemies {}, bolts {}, pickups {}",
+                game.enemies.len(),
+                game.projectiles.len(),
+                game.pickups.len()
+            ));
+        });
+}
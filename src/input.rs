@@ -0,0 +1,577 @@
+use anyhow::anyhow;
+use glam::Vec2;
+use macroquad::prelude::{
+    get_last_key_pressed, is_key_down, is_key_pressed, is_mouse_button_down,
+    is_mouse_button_pressed, mouse_position, KeyCode, MouseButton,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, fs, path::PathBuf, str::FromStr};
+
+/// Sticks report small values when at rest, anything below this counts as released.
+#[cfg(feature = "gamepad")]
+const DEADZONE: f32 = 0.2;
+
+/// How far from the player the right stick aims at full tilt, in world units.
+#[cfg(feature = "gamepad")]
+const AIM_DISTANCE: f32 = 48.;
+
+/// Everything the player can bind a key or button to.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Cast,
+    /// Sends the first enemy of the match settings to the opponent, and so on.
+    #[serde(rename = "send_1")]
+    Send1,
+    #[serde(rename = "send_2")]
+    Send2,
+    #[serde(rename = "send_3")]
+    Send3,
+    #[serde(rename = "send_4")]
+    Send4,
+    /// Joins or leaves the matchmaking queue.
+    Queue,
+    /// Opens the menu.
+    Pause,
+    /// Shows or hides frame rate, ping and entity counts.
+    ToggleDebug,
+}
+
+impl Action {
+    pub const ALL: [Self; 12] = [
+        Self::MoveUp,
+        Self::MoveDown,
+        Self::MoveLeft,
+        Self::MoveRight,
+        Self::Cast,
+        Self::Send1,
+        Self::Send2,
+        Self::Send3,
+        Self::Send4,
+        Self::Queue,
+        Self::Pause,
+        Self::ToggleDebug,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::MoveUp => "Move up",
+            Self::MoveDown => "Move down",
+            Self::MoveLeft => "Move left",
+            Self::MoveRight => "Move right",
+            Self::Cast => "Cast a bolt",
+            Self::Send1 => "Send enemy 1",
+            Self::Send2 => "Send enemy 2",
+            Self::Send3 => "Send enemy 3",
+            Self::Send4 => "Send enemy 4",
+            Self::Queue => "Search for a match",
+            Self::Pause => "Pause",
+            Self::ToggleDebug => "Show debug info",
+        }
+    }
+}
+
+/// Gamepad buttons by position, so bindings work the same for every layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+macro_rules! names {
+    ($type:ident: $($variant:ident),* $(,)?) => {
+        impl Name for $type {
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $(stringify!($variant) => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+
+            fn name(self) -> Option<&'static str> {
+                match self {
+                    $(Self::$variant => Some(stringify!($variant)),)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+trait Name: Sized {
+    fn from_name(name: &str) -> Option<Self>;
+    fn name(self) -> Option<&'static str>;
+}
+
+names!(KeyCode:
+    Space, Apostrophe, Comma, Minus, Period, Slash,
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    Semicolon, Equal,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    LeftBracket, Backslash, RightBracket, GraveAccent,
+    Escape, Enter, Tab, Backspace, Insert, Delete, Right, Left, Down, Up,
+    PageUp, PageDown, Home, End,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9,
+    KpDecimal, KpDivide, KpMultiply, KpSubtract, KpAdd, KpEnter,
+    LeftShift, LeftControl, LeftAlt, RightShift, RightControl, RightAlt,
+);
+
+names!(MouseButton: Left, Right, Middle);
+
+names!(PadButton:
+    South, East, West, North, LeftShoulder, RightShoulder, LeftTrigger, RightTrigger,
+    Select, Start, DPadUp, DPadDown, DPadLeft, DPadRight,
+);
+
+/// A key, mouse button or gamepad button, written as `W`, `MouseLeft` or `PadSouth`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Pad(PadButton),
+}
+
+impl FromStr for Binding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let binding = if let Some(button) = s.strip_prefix("Mouse") {
+            MouseButton::from_name(button).map(Self::Mouse)
+        } else if let Some(button) = s.strip_prefix("Pad") {
+            PadButton::from_name(button).map(Self::Pad)
+        } else {
+            KeyCode::from_name(s).map(Self::Key)
+        };
+        binding.ok_or_else(|| anyhow!("Unknown key or button '{}'", s))
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> Self {
+        binding.to_string()
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // captured bindings only ever come from the named keys and buttons
+        match self {
+            Self::Key(key) => write!(f, "{}", key.name().unwrap_or("Unknown")),
+            Self::Mouse(button) => write!(f, "Mouse{}", button.name().unwrap_or("Unknown")),
+            Self::Pad(button) => write!(f, "Pad{}", button.name().unwrap_or("Unknown")),
+        }
+    }
+}
+
+/// Which keys and buttons trigger each action, actions can have any number of them.
+/// Stored as one line per action, like `move_up = ["W", "Up", "PadDPadUp"]`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Bindings(BTreeMap<Action, Vec<Binding>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::{Key, Mouse, Pad};
+        Self(BTreeMap::from([
+            (Action::MoveUp, vec![Key(KeyCode::W), Key(KeyCode::Up)]),
+            (Action::MoveDown, vec![Key(KeyCode::S), Key(KeyCode::Down)]),
+            (Action::MoveLeft, vec![Key(KeyCode::A), Key(KeyCode::Left)]),
+            (
+                Action::MoveRight,
+                vec![Key(KeyCode::D), Key(KeyCode::Right)],
+            ),
+            (
+                Action::Cast,
+                vec![
+                    Key(KeyCode::Space),
+                    Mouse(MouseButton::Left),
+                    Pad(PadButton::South),
+                ],
+            ),
+            (Action::Send1, vec![Key(KeyCode::Key1)]),
+            (Action::Send2, vec![Key(KeyCode::Key2)]),
+            (Action::Send3, vec![Key(KeyCode::Key3)]),
+            (Action::Send4, vec![Key(KeyCode::Key4)]),
+            (Action::Queue, vec![Key(KeyCode::Q), Pad(PadButton::Select)]),
+            (
+                Action::Pause,
+                vec![Key(KeyCode::Escape), Pad(PadButton::Start)],
+            ),
+            (Action::ToggleDebug, vec![Key(KeyCode::F3)]),
+        ]))
+    }
+}
+
+impl Bindings {
+    fn get(&self, action: Action) -> &[Binding] {
+        self.0.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// Turns keyboard, mouse and gamepad state into actions.
+pub struct Input {
+    bindings: Bindings,
+    /// Where the rebinding menu saves the bindings.
+    path: PathBuf,
+    /// The action the next pressed key or button gets bound to, Escape cancels.
+    rebinding: Option<Action>,
+    /// Set for the frame a binding was captured in, so it doesn't trigger anything yet.
+    captured: bool,
+    /// Whether egui used the mouse or keyboard in the last frame, bindings don't see those.
+    ui_pointer: bool,
+    ui_keyboard: bool,
+    #[cfg(feature = "gamepad")]
+    gamepads: Option<gilrs::Gilrs>,
+    /// Gamepad buttons pressed since the last frame.
+    #[cfg(feature = "gamepad")]
+    pad_pressed: Vec<PadButton>,
+}
+
+impl Input {
+    /// Loads the bindings from `path`, using the defaults if the file doesn't exist.
+    pub fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let bindings = if path.exists() {
+            toml::from_str(&fs::read_to_string(&path)?)
+                .map_err(|err| anyhow!("{} is invalid: {}", path.display(), err))?
+        } else {
+            Bindings::default()
+        };
+        Ok(Self {
+            bindings,
+            path,
+            rebinding: None,
+            captured: false,
+            ui_pointer: false,
+            ui_keyboard: false,
+            #[cfg(feature = "gamepad")]
+            gamepads: gilrs::Gilrs::new()
+                .map_err(|err| log::warn!("Gamepads are not available: {}", err))
+                .ok(),
+            #[cfg(feature = "gamepad")]
+            pad_pressed: Vec::new(),
+        })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        fs::write(&self.path, toml::to_string(&self.bindings)?)?;
+        Ok(())
+    }
+
+    /// Has to be called once at the start of every frame.
+    pub fn update(&mut self) {
+        #[cfg(feature = "gamepad")]
+        {
+            self.pad_pressed.clear();
+            if let Some(gamepads) = &mut self.gamepads {
+                while let Some(event) = gamepads.next_event() {
+                    if let gilrs::EventType::ButtonPressed(button, _) = event.event {
+                        if let Some(button) = PadButton::from_gilrs(button) {
+                            self.pad_pressed.push(button);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.captured = false;
+        if let Some(action) = self.rebinding {
+            if is_key_pressed(KeyCode::Escape) {
+                // cancels, and `captured` keeps it from also opening the menu
+                self.rebinding = None;
+                self.captured = true;
+            } else if let Some(binding) = self.capture() {
+                self.rebinding = None;
+                self.captured = true;
+                let bindings = self.bindings.0.entry(action).or_default();
+                if !bindings.contains(&binding) {
+                    bindings.push(binding);
+                }
+                self.persist();
+            }
+        }
+    }
+
+    /// The first key or button pressed this frame, Escape is left for cancelling.
+    #[cfg_attr(not(feature = "gamepad"), allow(clippy::unused_self))]
+    fn capture(&self) -> Option<Binding> {
+        if let Some(key) =
+            get_last_key_pressed().filter(|key| *key != KeyCode::Escape && key.name().is_some())
+        {
+            return Some(Binding::Key(key));
+        }
+        if let Some(button) = [MouseButton::Left, MouseButton::Right, MouseButton::Middle]
+            .into_iter()
+            .find(|button| is_mouse_button_pressed(*button))
+        {
+            return Some(Binding::Mouse(button));
+        }
+        #[cfg(feature = "gamepad")]
+        if let Some(button) = self.pad_pressed.first() {
+            return Some(Binding::Pad(*button));
+        }
+        None
+    }
+
+    fn persist(&self) {
+        if let Err(err) = self.save() {
+            log::error!("Failed to save {}: {}", self.path.display(), err);
+        }
+    }
+
+    /// Names the first binding of the action, for hints in the UI.
+    pub fn describe(&self, action: Action) -> String {
+        self.bindings
+            .get(action)
+            .first()
+            .map_or_else(|| "(unbound)".to_owned(), ToString::to_string)
+    }
+
+    /// Tells which input egui used in this frame, clicking a button shouldn't also cast a spell.
+    pub fn set_ui_focus(&mut self, pointer: bool, keyboard: bool) {
+        self.ui_pointer = pointer;
+        self.ui_keyboard = keyboard;
+    }
+
+    /// Actions don't trigger while the menu is waiting for a key.
+    fn is_blocked(&self) -> bool {
+        self.rebinding.is_some() || self.captured
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        !self.is_blocked()
+            && self
+                .bindings
+                .get(action)
+                .iter()
+                .any(|binding| self.binding_down(*binding))
+    }
+
+    /// Whether the action was triggered this frame.
+    pub fn is_pressed(&self, action: Action) -> bool {
+        !self.is_blocked()
+            && self
+                .bindings
+                .get(action)
+                .iter()
+                .any(|binding| self.binding_pressed(*binding))
+    }
+
+    fn binding_down(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => !self.ui_keyboard && is_key_down(key),
+            Binding::Mouse(button) => !self.ui_pointer && is_mouse_button_down(button),
+            #[cfg(feature = "gamepad")]
+            Binding::Pad(button) => self.gamepads.as_ref().map_or(false, |gamepads| {
+                gamepads
+                    .gamepads()
+                    .any(|(_, gamepad)| gamepad.is_pressed(button.to_gilrs()))
+            }),
+            #[cfg(not(feature = "gamepad"))]
+            Binding::Pad(_) => false,
+        }
+    }
+
+    fn binding_pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => !self.ui_keyboard && is_key_pressed(key),
+            Binding::Mouse(button) => !self.ui_pointer && is_mouse_button_pressed(button),
+            #[cfg(feature = "gamepad")]
+            Binding::Pad(button) => self.pad_pressed.contains(&button),
+            #[cfg(not(feature = "gamepad"))]
+            Binding::Pad(_) => false,
+        }
+    }
+
+    /// Where the player wants to go, no longer than 1.
+    /// The left stick moves at any speed, the move actions at full speed.
+    pub fn movement(&self) -> Vec2 {
+        #[cfg(feature = "gamepad")]
+        if let Some(stick) = self.stick(gilrs::Axis::LeftStickX, gilrs::Axis::LeftStickY) {
+            return stick;
+        }
+        let axis = |negative, positive| match (self.is_down(negative), self.is_down(positive)) {
+            (true, false) => -1.,
+            (false, true) => 1.,
+            _ => 0.,
+        };
+        let movement = Vec2::new(
+            axis(Action::MoveLeft, Action::MoveRight),
+            axis(Action::MoveUp, Action::MoveDown),
+        );
+        if movement == Vec2::ZERO {
+            movement
+        } else {
+            movement.normalize()
+        }
+    }
+
+    /// The point the player aims at in world units, with the right stick
+    /// relative to `center`, otherwise the mouse cursor.
+    #[cfg_attr(not(feature = "gamepad"), allow(unused_variables, clippy::unused_self))]
+    pub fn aim(&self, screen_to_world: impl Fn(Vec2) -> Vec2, center: Vec2) -> Vec2 {
+        #[cfg(feature = "gamepad")]
+        if let Some(stick) = self.stick(gilrs::Axis::RightStickX, gilrs::Axis::RightStickY) {
+            return center + stick * AIM_DISTANCE;
+        }
+        let (x, y) = mouse_position();
+        screen_to_world(Vec2::new(x, y))
+    }
+
+    /// The position of a stick on the first gamepad that has one outside of the deadzone,
+    /// pointing down for positive y like the world does.
+    #[cfg(feature = "gamepad")]
+    fn stick(&self, x: gilrs::Axis, y: gilrs::Axis) -> Option<Vec2> {
+        self.gamepads.as_ref()?.gamepads().find_map(|(_, gamepad)| {
+            let stick = Vec2::new(gamepad.value(x), -gamepad.value(y));
+            let length = stick.length();
+            (length > DEADZONE).then(|| stick / length.max(1.))
+        })
+    }
+
+    /// Lists the bindings of every action, with buttons for changing them.
+    pub fn rebinding_ui(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        egui::Grid::new("controls").show(ui, |ui| {
+            for action in Action::ALL {
+                ui.label(action.label());
+                let bindings = self.bindings.0.entry(action).or_default();
+                let text = bindings
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                ui.label(if text.is_empty() { "-" } else { &text });
+                if self.rebinding == Some(action) {
+                    ui.horizontal(|ui| {
+                        ui.label("Press a key or button...");
+                        if ui
+                            .button("Cancel")
+                            .on_hover_text("Or press Escape")
+                            .clicked()
+                        {
+                            self.rebinding = None;
+                        }
+                    });
+                } else if ui.button("Add").clicked() {
+                    self.rebinding = Some(action);
+                }
+                if ui.button("Clear").clicked() {
+                    bindings.clear();
+                    changed = true;
+                }
+                ui.end_row();
+            }
+        });
+        if ui.button("Reset to defaults").clicked() {
+            self.bindings = Bindings::default();
+            self.rebinding = None;
+            changed = true;
+        }
+        if changed {
+            self.persist();
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl PadButton {
+    fn to_gilrs(self) -> gilrs::Button {
+        match self {
+            Self::South => gilrs::Button::South,
+            Self::East => gilrs::Button::East,
+            Self::West => gilrs::Button::West,
+            Self::North => gilrs::Button::North,
+            Self::LeftShoulder => gilrs::Button::LeftTrigger,
+            Self::RightShoulder => gilrs::Button::RightTrigger,
+            Self::LeftTrigger => gilrs::Button::LeftTrigger2,
+            Self::RightTrigger => gilrs::Button::RightTrigger2,
+            Self::Select => gilrs::Button::Select,
+            Self::Start => gilrs::Button::Start,
+            Self::DPadUp => gilrs::Button::DPadUp,
+            Self::DPadDown => gilrs::Button::DPadDown,
+            Self::DPadLeft => gilrs::Button::DPadLeft,
+            Self::DPadRight => gilrs::Button::DPadRight,
+        }
+    }
+
+    fn from_gilrs(button: gilrs::Button) -> Option<Self> {
+        Some(match button {
+            gilrs::Button::South => Self::South,
+            gilrs::Button::East => Self::East,
+            gilrs::Button::West => Self::West,
+            gilrs::Button::North => Self::North,
+            gilrs::Button::LeftTrigger => Self::LeftShoulder,
+            gilrs::Button::RightTrigger => Self::RightShoulder,
+            gilrs::Button::LeftTrigger2 => Self::LeftTrigger,
+            gilrs::Button::RightTrigger2 => Self::RightTrigger,
+            gilrs::Button::Select => Self::Select,
+            gilrs::Button::Start => Self::Start,
+            gilrs::Button::DPadUp => Self::DPadUp,
+            gilrs::Button::DPadDown => Self::DPadDown,
+            gilrs::Button::DPadLeft => Self::DPadLeft,
+            gilrs::Button::DPadRight => Self::DPadRight,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bindings_round_trip_through_their_names() {
+        let keys = [KeyCode::W, KeyCode::Key1, KeyCode::Escape, KeyCode::KpEnter];
+        let bindings = keys
+            .into_iter()
+            .map(Binding::Key)
+            .chain([MouseButton::Left, MouseButton::Middle].map(Binding::Mouse))
+            .chain([PadButton::South, PadButton::DPadUp].map(Binding::Pad));
+        for binding in bindings {
+            assert_eq!(binding.to_string().parse::<Binding>().unwrap(), binding);
+        }
+        assert_eq!(Binding::Mouse(MouseButton::Left).to_string(), "MouseLeft");
+        assert_eq!(Binding::Pad(PadButton::Start).to_string(), "PadStart");
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert!("MouseBack".parse::<Binding>().is_err());
+        assert!("PadHome".parse::<Binding>().is_err());
+        assert!("Unknown".parse::<Binding>().is_err());
+    }
+
+    #[test]
+    fn default_bindings_survive_the_config_file() {
+        let bindings = Bindings::default();
+        let parsed: Bindings = toml::from_str(&toml::to_string(&bindings).unwrap()).unwrap();
+        assert_eq!(parsed.0, bindings.0);
+    }
+}
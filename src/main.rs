@@ -1,27 +1,71 @@
 #![warn(clippy::pedantic, clippy::perf)]
 
+mod ai;
+mod animation;
+mod assets;
+mod camera;
+mod enemy;
+mod hud;
+mod input;
+mod map;
+mod pickup;
+mod projectile;
+mod results;
 mod tcpstream;
+mod ui;
 mod ws;
 
+use animation::{AnimationKind, Animator};
+use anyhow::anyhow;
+use assets::{Assets, SpriteSheet};
+use camera::{confine, WorldCamera};
 use clap::Parser;
+use enemy::{Enemy, SEPARATION_RADIUS};
 use glam::Vec2;
+use input::{Action, Input};
 use lazy_static::lazy_static;
 use macroquad::{
+    audio::play_sound_once,
     prelude::{
         clear_background, color_u8,
         coroutines::{start_coroutine, wait_seconds},
-        draw_rectangle, draw_texture_ex, is_key_down, next_frame, screen_height, screen_width,
-        Color, DrawTextureParams, KeyCode, Rect, Texture2D, BLACK, WHITE,
+        draw_circle_lines, draw_texture_ex, get_frame_time, get_time, next_frame, set_camera,
+        set_default_camera, Color, DrawTextureParams, BLACK, WHITE,
     },
 };
-use shared::{deserialize, serialize, ClientMessage, ServerMessage, Uuid, SPEED};
-use std::{collections::HashMap, io, sync::Arc};
+use map::TileMap;
+use pickup::Pickup;
+use projectile::Projectile;
+use results::{Choice, MatchResults};
+use serde::Deserialize;
+use shared::{
+    deserialize, serialize, ClientMessage, MatchSettings, Movement, PickupKind, Rng, ServerMessage,
+    SpatialGrid, Uuid, DEFAULT_MAP,
+};
+use std::{collections::HashMap, fs, io, path::PathBuf, sync::Arc};
+use ui::{Panel, UiLayer};
 use ws::Connection;
 
-const CHAR_WIDTH: f32 = 16.;
-const CHAR_HEIGHT: f32 = 16.;
+/// Enemies only spawn on tiles at least this far from the player, in world units.
+const SPAWN_DISTANCE: f32 = 64.;
+
+/// Sets enemies apart from the player while they share a sprite sheet.
+const ENEMY_TINT: Color = Color::new(1., 0.6, 0.6, 1.);
+
+/// After a hitch the simulation skips ahead instead of catching up on more than this many seconds.
+const MAX_CATCH_UP: f32 = 0.25;
+
+/// Cell size of the grid that finds what enemies overlap, in world units.
+const GRID_CELL: f32 = 32.;
 
-#[derive(Clone, Copy, Debug)]
+/// How quickly mana comes back.
+const MANA_PER_SECOND: f32 = 5.;
+
+/// How often the round trip time to the server is measured.
+const PING_SECONDS: f64 = 2.;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
 pub enum Direction {
     Up,
     UpRight,
@@ -33,33 +77,130 @@ pub enum Direction {
     UpLeft,
 }
 
+impl Direction {
+    /// The closest of the eight directions, `None` for the zero vector.
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_vector(vector: Vec2) -> Option<Self> {
+        if vector == Vec2::ZERO {
+            return None;
+        }
+        // y points down, so the angle goes clockwise starting at the right
+        let octant = (vector.y.atan2(vector.x) / std::f32::consts::FRAC_PI_4).round() as i32;
+        Some(match octant.rem_euclid(8) {
+            0 => Self::Right,
+            1 => Self::DownRight,
+            2 => Self::Down,
+            3 => Self::DownLeft,
+            4 => Self::Left,
+            5 => Self::UpLeft,
+            6 => Self::Up,
+            _ => Self::UpRight,
+        })
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct PlayerState {
     name: String,
     id: Uuid,
     seed: u64,
-    anim_id: usize,
+    animation: Animator,
     position: Vec2,
+    /// In world units per second.
+    velocity: Vec2,
+    /// Ticks simulated this match.
+    ticks: u32,
+    /// How far the player moved this match, not counting jumps across wrapping edges.
+    moved: Vec2,
+    /// Where the player is aiming, in world units.
+    aim: Vec2,
     kills: usize,
+    /// Kills spent on sending enemies to the opponent.
+    spent: usize,
+    /// Health taken from enemies this match.
+    damage_dealt: u32,
+    health: u32,
+    mana: f32,
+    /// Enemies can't hurt the player again before this time.
+    invulnerable_until: f64,
+    /// Bolts hurt more until this time.
+    damage_buff_until: f64,
+    /// The player is faster until this time.
+    speed_buff_until: f64,
+    /// The next bolt can be cast at this time.
+    cast_ready_at: f64,
+    rating: u32,
+}
+
+#[derive(Default)]
+pub struct LoginForm {
+    name: String,
+    password: String,
+    error: Option<String>,
+}
+
+/// A challenge this player sent, until it's answered.
+pub struct SentChallenge {
+    name: String,
+    /// Known once the server passed the challenge on.
+    request_id: Option<Uuid>,
+    denied: bool,
 }
 
 pub struct RemotePlayerState {
     name: String,
+    rating: u32,
 }
 
+#[allow(clippy::struct_excessive_bools)]
 pub struct Game {
+    pub logged_in: bool,
+    /// The server closed the connection, nothing is sent until the player reconnects.
+    pub disconnected: bool,
+    /// Set by the login screen to connect again after being disconnected.
+    pub reconnect: bool,
+    pub login: LoginForm,
     pub player_state: PlayerState,
     pub players: HashMap<Uuid, RemotePlayerState>,
-    pub texture: Texture2D,
+    pub opponent: Option<Uuid>,
+    /// The match being played, set together with `opponent`.
+    pub match_id: Option<Uuid>,
+    /// Challenges from other players by request, with the name of who sent them.
+    pub challenges: HashMap<Uuid, String>,
+    pub challenge: Option<SentChallenge>,
+    pub settings: MatchSettings,
+    pub queued: bool,
+    pub shutdown_in: Option<u64>,
+    pub announcement: Option<String>,
+    /// Why the server didn't accept the last name change, until it's dismissed.
+    pub name_error: Option<String>,
+    pub outgoing: Vec<ClientMessage>,
+    pub wizard: SpriteSheet,
+    pub assets: Assets,
+    pub map: TileMap,
+    pub enemies: Vec<Enemy>,
+    /// The enemies by where they are, filed again every tick.
+    pub grid: SpatialGrid,
+    pub projectiles: Vec<Projectile>,
+    pub pickups: Vec<Pickup>,
+    /// The last match, until the player leaves the results or a new match starts.
+    pub results: Option<MatchResults>,
+    /// Seeded by the match, so enemies act the same every time for the same seed.
+    pub rng: Rng,
+    pub input: Input,
+    /// The menu blocks all actions while it's open.
+    pub menu_open: bool,
+    pub show_debug: bool,
+    pub ui: UiLayer,
+    pub match_started_at: f64,
+    /// Round trip time to the server in seconds, once it answered a ping.
+    pub ping: Option<f64>,
+    pub ping_sent_at: f64,
+    /// Seconds that passed but weren't simulated yet, always less than a tick.
+    pub unsimulated: f32,
     pub quit: bool,
 }
 
-fn draw_box(pos: Vec2, size: Vec2) {
-    let dimension = size * 2.;
-    let upper_left = pos - size;
-    draw_rectangle(upper_left.x, upper_left.y, dimension.x, dimension.y, BLACK);
-}
-
 #[must_use]
 pub fn vec2_from_angle(angle: f32) -> Vec2 {
     let angle = angle - std::f32::consts::FRAC_PI_2;
@@ -68,12 +209,57 @@ pub fn vec2_from_angle(angle: f32) -> Vec2 {
 
 impl Game {
     async fn new() -> anyhow::Result<Self> {
-        let texture =
-            Texture2D::from_file_with_format(include_bytes!("../assets/8Bit Wizard.png"), None);
+        let assets = Assets::load(&ARGS.assets).await?;
+        assets.install_fonts();
+        let wizard = assets
+            .sprite_sheet("wizard")
+            .cloned()
+            .ok_or_else(|| anyhow!("The asset manifest has no sprite sheet named wizard"))?;
+        let map = assets
+            .map(DEFAULT_MAP)
+            .cloned()
+            .ok_or_else(|| anyhow!("The asset manifest has no map named {}", DEFAULT_MAP))?;
         let game = Self {
-            player_state: PlayerState::default(),
+            logged_in: false,
+            disconnected: false,
+            reconnect: false,
+            login: LoginForm {
+                name: ARGS.name.clone().unwrap_or_default(),
+                ..LoginForm::default()
+            },
+            player_state: PlayerState {
+                position: map.spawn,
+                health: MatchSettings::default().health,
+                ..PlayerState::default()
+            },
             players: HashMap::new(),
-            texture,
+            opponent: None,
+            match_id: None,
+            challenges: HashMap::new(),
+            challenge: None,
+            settings: MatchSettings::default(),
+            queued: false,
+            shutdown_in: None,
+            announcement: None,
+            name_error: None,
+            outgoing: Vec::new(),
+            wizard,
+            assets,
+            grid: SpatialGrid::new(map.size(), GRID_CELL),
+            map,
+            enemies: Vec::new(),
+            projectiles: Vec::new(),
+            pickups: Vec::new(),
+            results: None,
+            rng: Rng::new(0),
+            input: Input::load(ARGS.controls.clone())?,
+            menu_open: false,
+            show_debug: false,
+            ui: UiLayer::default(),
+            match_started_at: 0.,
+            ping: None,
+            ping_sent_at: f64::NEG_INFINITY,
+            unsimulated: 0.,
             quit: false,
         };
         Ok(game)
@@ -83,146 +269,908 @@ impl Game {
         match msg {
             ServerMessage::Welcome { id } => {
                 self.player_state.id = id;
+                self.logged_in = false;
+                if let Some((name, token)) = load_session() {
+                    self.login.name.clone_from(&name);
+                    self.outgoing.push(ClientMessage::Resume { name, token });
+                }
+            }
+            ServerMessage::LoggedIn { token } => {
+                self.logged_in = true;
+                self.login.password.clear();
+                self.login.error = None;
+                if let Err(err) = save_session(&self.login.name, &token) {
+                    log::warn!("Failed to cache session: {}", err);
+                }
+            }
+            ServerMessage::LoginFailed { reason } => {
+                clear_session();
+                self.login.error = Some(reason);
+            }
+            ServerMessage::Announcement { text } => {
+                log::info!("Announcement: {}", text);
+                self.announcement = Some(text);
+            }
+            ServerMessage::ShuttingDown { seconds } => {
+                log::warn!("The server shuts down in {} seconds", seconds);
+                self.shutdown_in = Some(seconds);
+            }
+            ServerMessage::Disconnected { reason } => {
+                log::error!("Disconnected by the server: {}", reason);
+                self.disconnected(reason);
+            }
+            ServerMessage::Error { code } => {
+                log::error!("The server rejected a message: {:?}", code);
+            }
+            ServerMessage::AuthenticationRequired => {
+                log::warn!("Server requires logging in first");
+                self.logged_in = false;
             }
             ServerMessage::GoodBye(id) => {
                 if id != self.player_state.id {
                     self.players.remove(&id);
                 }
             }
-            ServerMessage::PlayerChangedName { id, new_name } => {
+            ServerMessage::PlayerChangedName { id, new_name } => self.rename(id, new_name),
+            ServerMessage::PlayerRatingChanged { id, rating } => {
+                if self.player_state.id == id {
+                    self.player_state.rating = rating;
+                } else if let Some(player) = self.players.get_mut(&id) {
+                    player.rating = rating;
+                }
+            }
+            ServerMessage::MatchStarted {
+                match_id,
+                opponent,
+                seed,
+                settings,
+            } => self.start_match(match_id, opponent, seed, settings),
+            ServerMessage::Update { spawns } => {
+                for kind in spawns {
+                    self.spawn_enemy(kind);
+                }
+            }
+            ServerMessage::Finish { .. }
+            | ServerMessage::RematchOffered
+            | ServerMessage::RematchCancelled { .. } => self.handle_results(msg),
+            ServerMessage::PlayerJoined { id, name, rating } => {
                 if self.player_state.id == id {
-                    self.player_state.name = new_name;
+                    self.player_state.name = name;
+                    self.player_state.rating = rating;
                 } else {
-                    if let Some(player) = self.players.get_mut(&id) {
-                        player.name = new_name;
-                    }
+                    self.players.insert(id, RemotePlayerState { name, rating });
+                }
+            }
+            ServerMessage::NameNotAvailable { name } => {
+                log::error!("The name '{}' is already in use", name);
+                self.name_error = Some(format!("The name '{name}' is already in use"));
+            }
+            ServerMessage::NameRejected { name, reason } => {
+                log::error!("The name '{}' was rejected: {}", name, reason);
+                self.name_error = Some(reason);
+            }
+            ServerMessage::ChallengeReceived { .. }
+            | ServerMessage::ChallengeDenied { .. }
+            | ServerMessage::RequestReceived { .. } => self.handle_challenge(msg),
+            ServerMessage::Pong => self.ping = Some(get_time() - self.ping_sent_at),
+        }
+    }
+
+    /// Goes back to the login screen, which shows the reason and offers to reconnect.
+    /// Whatever was going on is over, the server already ended it.
+    fn disconnected(&mut self, reason: String) {
+        self.logged_in = false;
+        self.disconnected = true;
+        self.login.error = Some(reason);
+        self.outgoing.clear();
+        self.players.clear();
+        self.challenges.clear();
+        self.challenge = None;
+        self.queued = false;
+        self.opponent = None;
+        self.match_id = None;
+        self.results = None;
+        self.name_error = None;
+        self.enemies.clear();
+        self.projectiles.clear();
+        self.pickups.clear();
+    }
+
+    /// Challenges from other players and the answer to the one this player sent.
+    fn handle_challenge(&mut self, msg: ServerMessage) {
+        match msg {
+            ServerMessage::ChallengeReceived { request_id, name } => {
+                log::info!("{} challenged you", name);
+                self.challenges.insert(request_id, name);
+            }
+            ServerMessage::RequestReceived { request_id } => {
+                if let Some(challenge) = &mut self.challenge {
+                    challenge.request_id = Some(request_id);
+                }
+            }
+            ServerMessage::ChallengeDenied { request_id } => {
+                self.challenges.remove(&request_id);
+                if let Some(challenge) = self
+                    .challenge
+                    .as_mut()
+                    .filter(|challenge| challenge.request_id.is_none_or(|id| id == request_id))
+                {
+                    challenge.denied = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn challenge_player(&mut self, name: String) {
+        self.outgoing
+            .push(ClientMessage::ChallengePlayer { name: name.clone() });
+        self.challenge = Some(SentChallenge {
+            name,
+            request_id: None,
+            denied: false,
+        });
+    }
+
+    /// Switches to the map and puts the player on its spawn, unknown maps fall back to the default one.
+    fn load_map(&mut self, name: &str) {
+        let map = self.assets.map(name).or_else(|| {
+            log::error!("Unknown map '{}', using '{}' instead", name, DEFAULT_MAP);
+            self.assets.map(DEFAULT_MAP)
+        });
+        if let Some(map) = map {
+            self.map = map.clone();
+            self.grid = SpatialGrid::new(self.map.size(), GRID_CELL);
+            self.player_state.position = self.map.spawn;
+            self.player_state.velocity = Vec2::ZERO;
+        }
+    }
+
+    /// Leaves the results of the last match behind and starts on the new map.
+    fn start_match(&mut self, match_id: Uuid, opponent: Uuid, seed: u64, settings: MatchSettings) {
+        self.queued = false;
+        self.load_map(&settings.arena.map);
+        self.settings = settings;
+        self.opponent = Some(opponent);
+        self.match_id = Some(match_id);
+        self.challenges.clear();
+        self.challenge = None;
+        self.player_state.seed = seed;
+        self.player_state.kills = 0;
+        self.player_state.spent = 0;
+        self.player_state.damage_dealt = 0;
+        self.player_state.ticks = 0;
+        self.player_state.moved = Vec2::ZERO;
+        self.results = None;
+        self.revive();
+        self.enemies.clear();
+        self.projectiles.clear();
+        self.pickups.clear();
+        self.rng = Rng::new(seed);
+        self.match_started_at = get_time();
+    }
+
+    fn opponent_name(&self) -> String {
+        self.opponent
+            .and_then(|id| self.players.get(&id))
+            .map_or_else(|| "your opponent".to_owned(), |player| player.name.clone())
+    }
+
+    /// The end of the match and what happens with the rematch on the results screen.
+    #[allow(clippy::cast_precision_loss)]
+    fn handle_results(&mut self, msg: ServerMessage) {
+        match msg {
+            ServerMessage::Finish {
+                enemy_kills,
+                winner,
+                enemies_sent,
+                enemies_received,
+                seconds,
+                rating_change,
+                rematch_seconds,
+            } => self.finish(MatchResults {
+                won: winner.map(|id| id == self.player_state.id),
+                opponent: self.opponent_name(),
+                kills: self.player_state.kills,
+                enemy_kills,
+                enemies_sent,
+                enemies_received,
+                damage_dealt: self.player_state.damage_dealt,
+                seconds,
+                rating_change,
+                rematch_until: get_time() + rematch_seconds as f64,
+                rematch_requested: false,
+                opponent_wants_rematch: false,
+                opponent_left: false,
+            }),
+            ServerMessage::RematchOffered => {
+                if let Some(results) = &mut self.results {
+                    results.opponent_wants_rematch = true;
                 }
             }
-            ServerMessage::Update { spawns } => todo!(),
-            ServerMessage::Finish { enemy_kills } => todo!(),
-            ServerMessage::PlayerJoined { id, name } => {
-                self.players.insert(id, RemotePlayerState { name });
+            ServerMessage::RematchCancelled { expired: true } => self.results = None,
+            ServerMessage::RematchCancelled { expired: false } => {
+                if let Some(results) = &mut self.results {
+                    results.opponent_left = true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, results: MatchResults) {
+        let result = match results.won {
+            Some(true) => "you won",
+            Some(false) => "you lost",
+            None => "it was aborted",
+        };
+        log::info!(
+            "Match finished, {}. You killed {} enemies, your opponent killed {}",
+            result,
+            results.kills,
+            results.enemy_kills
+        );
+        self.results = Some(results);
+        self.opponent = None;
+        self.match_id = None;
+        self.revive();
+        self.enemies.clear();
+        self.projectiles.clear();
+        self.pickups.clear();
+    }
+
+    /// Restores full health and mana and ends a death animation and buffs,
+    /// for the start and end of matches.
+    fn revive(&mut self) {
+        let state = &mut self.player_state;
+        state.health = self.settings.health;
+        state.mana = self.settings.mana;
+        state.invulnerable_until = 0.;
+        state.damage_buff_until = 0.;
+        state.speed_buff_until = 0.;
+        state.cast_ready_at = 0.;
+        state.animation.play(AnimationKind::Idle, get_time());
+    }
+
+    fn unspent_kills(&self) -> usize {
+        self.player_state.kills - self.player_state.spent
+    }
+
+    /// Spends kills on sending the enemy at `index` of the match settings to the opponent.
+    fn send_enemy(&mut self, index: usize) {
+        let Some(cost) = self.settings.enemies.get(index).map(|enemy| enemy.cost) else {
+            return;
+        };
+        if self.unspent_kills() < cost {
+            return;
+        }
+        let Some(state) = self.state() else {
+            return;
+        };
+        self.player_state.spent += cost;
+        // the server only lets kills it already knows about be spent
+        self.outgoing.push(state);
+        self.outgoing.push(ClientMessage::SendEnemy { index });
+    }
+
+    /// What the server needs to know about the match every tick, `None` outside of matches.
+    fn state(&self) -> Option<ClientMessage> {
+        self.match_id.map(|match_id| ClientMessage::State {
+            match_id,
+            kills: self.player_state.kills,
+            ticks: self.player_state.ticks,
+            moved: self.player_state.moved,
+        })
+    }
+
+    /// Puts an enemy the opponent sent on a random open tile away from the player.
+    fn spawn_enemy(&mut self, kind: usize) {
+        let Some(enemy_type) = self.settings.enemies.get(kind) else {
+            log::error!("The opponent sent an unknown enemy: {}", kind);
+            return;
+        };
+        let player = self.player_state.position;
+        let tiles: Vec<Vec2> = self
+            .map
+            .open_tiles()
+            .into_iter()
+            .filter(|tile| tile.distance(player) >= SPAWN_DISTANCE)
+            .collect();
+        if tiles.is_empty() {
+            log::warn!("There is no room for an enemy on this map");
+            return;
+        }
+        let position = tiles[self.rng.index(tiles.len())];
+        let sheet = self.enemy_sheet(kind);
+        let size = Vec2::new(sheet.frame_width, sheet.frame_height);
+        self.enemies
+            .push(Enemy::new(kind, enemy_type, position, size));
+    }
+
+    /// Enemies whose sprite sheet isn't in the assets look like the wizard.
+    fn enemy_sheet(&self, kind: usize) -> &SpriteSheet {
+        self.settings
+            .enemies
+            .get(kind)
+            .and_then(|enemy| self.assets.sprite_sheet(&enemy.sprite))
+            .unwrap_or(&self.wizard)
+    }
+
+    fn rename(&mut self, id: Uuid, new_name: String) {
+        if self.player_state.id == id {
+            // Sessions are resumed by name, so the cached one has to follow the rename
+            if let Some((_, token)) = load_session() {
+                if let Err(err) = save_session(&new_name, &token) {
+                    log::warn!("Failed to cache session: {}", err);
+                }
             }
-            ServerMessage::NameNotAvailable { name } => todo!(),
-            ServerMessage::ChallengeReceived { request_id, name } => todo!(),
-            ServerMessage::ChallengeDenied { request_id } => todo!(),
-            ServerMessage::RequestReceived { request_id } => todo!(),
+            self.login.name.clone_from(&new_name);
+            self.player_state.name = new_name;
+        } else if let Some(player) = self.players.get_mut(&id) {
+            player.name = new_name;
         }
     }
 
     fn update(&mut self) {
-        if is_key_down(KeyCode::Escape) {
-            self.quit = true;
-        }
-
-        if is_key_down(KeyCode::Space) {
-            self.player_state.kills += 1;
-        }
-
-        let direction = match (
-            is_key_down(KeyCode::A),
-            is_key_down(KeyCode::W),
-            is_key_down(KeyCode::S),
-            is_key_down(KeyCode::D),
-        ) {
-            // left, up, down, right
-            (true, true, true, true) => None,
-            (true, true, false, true) => Some(Direction::Up),
-            (true, true, true, false) => Some(Direction::Left),
-            (true, true, false, false) => Some(Direction::UpLeft),
-            (true, false, true, true) => Some(Direction::Down),
-            (true, false, false, true) => None,
-            (true, false, true, false) => Some(Direction::DownLeft),
-            (true, false, false, false) => Some(Direction::Left),
-            (false, true, true, true) => Some(Direction::Right),
-            (false, true, false, true) => Some(Direction::UpRight),
-            (false, true, true, false) => None,
-            (false, true, false, false) => Some(Direction::Up),
-            (false, false, true, true) => Some(Direction::DownRight),
-            (false, false, false, true) => Some(Direction::Right),
-            (false, false, true, false) => Some(Direction::Down),
-            (false, false, false, false) => None,
+        self.input.update();
+        if self.input.is_pressed(Action::Pause) {
+            self.menu_open = !self.menu_open;
+        }
+        if self.input.is_pressed(Action::ToggleDebug) {
+            self.show_debug = !self.show_debug;
+        }
+        if get_time() - self.ping_sent_at >= PING_SECONDS {
+            self.ping_sent_at = get_time();
+            self.outgoing.push(ClientMessage::Ping);
+        }
+        let active = !self.menu_open && self.results.is_none() && self.player_state.health > 0;
+
+        let casting = active && self.input.is_down(Action::Cast);
+        if active
+            && self.input.is_pressed(Action::Cast)
+            && self.player_state.mana >= projectile::MANA_COST
+            && get_time() >= self.player_state.cast_ready_at
+        {
+            if let Some(sound) = self.assets.sound("cast") {
+                play_sound_once(sound);
+            }
+            let size = Vec2::new(self.wizard.frame_width, self.wizard.frame_height);
+            let state = &mut self.player_state;
+            state.mana -= projectile::MANA_COST;
+            state.cast_ready_at = get_time() + projectile::COOLDOWN_SECONDS;
+            let damage = if get_time() < state.damage_buff_until {
+                projectile::DAMAGE + self.settings.pickups.damage_bonus
+            } else {
+                projectile::DAMAGE
+            };
+            self.projectiles.extend(Projectile::new(
+                state.position + size / 2.,
+                state.aim,
+                self.settings.bolt_speed,
+                damage,
+                self.settings.tick(),
+            ));
+        }
+        for (index, action) in [Action::Send1, Action::Send2, Action::Send3, Action::Send4]
+            .into_iter()
+            .enumerate()
+        {
+            if active && self.input.is_pressed(action) {
+                self.send_enemy(index);
+            }
+        }
+
+        if active && self.input.is_pressed(Action::Queue) && self.opponent.is_none() {
+            self.queued = !self.queued;
+            self.outgoing.push(if self.queued {
+                ClientMessage::JoinQueue
+            } else {
+                ClientMessage::LeaveQueue
+            });
+        }
+
+        let movement = if active {
+            self.input.movement()
+        } else {
+            Vec2::ZERO
+        };
+        let direction = Direction::from_vector(movement);
+
+        let now = get_time();
+        let animation = &mut self.player_state.animation;
+        if let Some(direction) = direction {
+            animation.face(direction);
+        }
+        // being hurt plays out before anything else, dying is final
+        let interruptible = match animation.kind() {
+            AnimationKind::Die => false,
+            AnimationKind::Hurt => animation.is_finished(&self.wizard.animations, now),
+            _ => true,
         };
+        if interruptible {
+            let kind = if casting {
+                AnimationKind::Cast
+            } else if direction.is_some() {
+                AnimationKind::Walk
+            } else {
+                AnimationKind::Idle
+            };
+            animation.play(kind, now);
+        }
 
-        self.player_state.anim_id = 0;
+        self.simulate(movement);
 
-        match direction {
-            Some(Direction::Up) => self.player_state.position.y -= SPEED,
-            Some(Direction::UpRight) => {
-                self.player_state.position.x += SPEED;
-                self.player_state.position.y -= SPEED;
+        let size = Vec2::new(self.wizard.frame_width, self.wizard.frame_height);
+        let state = &mut self.player_state;
+        let camera = WorldCamera::new(self.map.size());
+        state.aim = self.input.aim(
+            |point| camera.screen_to_world(point),
+            state.position + size / 2.,
+        );
+    }
+
+    /// Moves the player in fixed ticks, which makes it independent of the frame rate.
+    fn simulate(&mut self, input: Vec2) {
+        let tick = self.settings.tick();
+        self.unsimulated = (self.unsimulated + get_frame_time()).min(MAX_CATCH_UP);
+        let size = Vec2::new(self.wizard.frame_width, self.wizard.frame_height);
+        let now = get_time();
+        while self.unsimulated >= tick {
+            self.unsimulated -= tick;
+            self.step_player(input, size, tick, now);
+            self.collect_pickups(size, now);
+            let player = self.player_state.position + size / 2.;
+            self.file_enemies();
+            let centers: Vec<Vec2> = self.enemies.iter().map(Enemy::center).collect();
+            for (index, enemy) in self.enemies.iter_mut().enumerate() {
+                let others = self
+                    .grid
+                    .query(centers[index], Vec2::splat(SEPARATION_RADIUS))
+                    .filter(|other| *other != index)
+                    .map(|other| centers[other]);
+                let separation = ai::separation(centers[index], others, SEPARATION_RADIUS);
+                enemy.step(
+                    &self.settings,
+                    &self.map,
+                    player,
+                    separation,
+                    &mut self.rng,
+                    now,
+                );
             }
-            Some(Direction::Right) => self.player_state.position.x += SPEED,
-            Some(Direction::DownRight) => {
-                self.player_state.position.x += SPEED;
-                self.player_state.position.y += SPEED;
+            self.file_enemies();
+            self.take_contact_damage(size, now);
+            self.step_projectiles(tick);
+        }
+    }
+
+    fn file_enemies(&mut self) {
+        self.grid.clear();
+        for (index, enemy) in self.enemies.iter().enumerate() {
+            self.grid.insert(index, enemy.center(), enemy.size / 2.);
+        }
+    }
+
+    /// Bolts hurt the first enemy they touch, enemies without health left count as kills.
+    /// The grid has to be filed with the enemies as they are now.
+    fn step_projectiles(&mut self, tick: f32) {
+        let enemies = &mut self.enemies;
+        let damage_dealt = &mut self.player_state.damage_dealt;
+        let grid = &self.grid;
+        let map = &self.map;
+        self.projectiles.retain_mut(|projectile| {
+            if !projectile.step(map, tick) {
+                return false;
             }
-            Some(Direction::Down) => self.player_state.position.y += SPEED,
-            Some(Direction::DownLeft) => {
-                self.player_state.position.x -= SPEED;
-                self.player_state.position.y += SPEED;
+            let hit = grid
+                .query(projectile.position, Vec2::splat(projectile::RADIUS))
+                .find(|index| enemies[*index].health > 0 && projectile.hits(&enemies[*index]));
+            match hit {
+                Some(index) => {
+                    *damage_dealt += projectile.strike(&mut enemies[index]);
+                    false
+                }
+                None => true,
             }
-            Some(Direction::Left) => self.player_state.position.x -= SPEED,
-            Some(Direction::UpLeft) => {
-                self.player_state.position.x -= SPEED;
-                self.player_state.position.y -= SPEED;
+        });
+        for enemy in self.enemies.iter().filter(|enemy| enemy.health == 0) {
+            if self.rng.next_f32() < self.settings.pickups.drop_chance {
+                let kind = PickupKind::ALL[self.rng.index(PickupKind::ALL.len())];
+                self.pickups.push(Pickup {
+                    kind,
+                    position: enemy.center(),
+                });
             }
-            None => (),
         }
+        let before = self.enemies.len();
+        self.enemies.retain(|enemy| enemy.health > 0);
+        self.player_state.kills += before - self.enemies.len();
+    }
+
+    fn collect_pickups(&mut self, size: Vec2, now: f64) {
+        let state = &mut self.player_state;
+        if state.health == 0 {
+            return;
+        }
+        let settings = &self.settings;
+        let buff_until = now + f64::from(settings.pickups.buff_seconds);
+        self.pickups.retain(|pickup| {
+            if !pickup.touches(state.position, size) {
+                return true;
+            }
+            match pickup.kind {
+                PickupKind::Health => {
+                    state.health = (state.health + settings.pickups.heal).min(settings.health);
+                }
+                PickupKind::Mana => {
+                    state.mana = (state.mana + settings.pickups.mana).min(settings.mana);
+                }
+                PickupKind::Damage => state.damage_buff_until = buff_until,
+                PickupKind::Speed => state.speed_buff_until = buff_until,
+            }
+            false
+        });
+    }
+
+    fn step_player(&mut self, input: Vec2, size: Vec2, tick: f32, now: f64) {
+        let state = &mut self.player_state;
+        if state.health > 0 {
+            state.mana = (state.mana + MANA_PER_SECOND * tick).min(self.settings.mana);
+        }
+        let movement = if now < state.speed_buff_until {
+            Movement {
+                max_speed: self.settings.movement.max_speed
+                    * self.settings.pickups.speed_multiplier,
+                ..self.settings.movement
+            }
+        } else {
+            self.settings.movement
+        };
+        let velocity = movement.step(state.velocity, input, tick);
+        let moved = self
+            .map
+            .move_and_collide(state.position, size, velocity * tick);
+        // walls take away the speed towards them
+        state.velocity = (moved - state.position) / tick;
+        state.moved += moved - state.position;
+        state.ticks += 1;
+        state.position = moved;
+        confine(
+            self.settings.arena.edges,
+            self.map.size(),
+            &mut state.position,
+            size,
+        );
+    }
 
-        if self.player_state.position.x > screen_width() {
-            self.player_state.position.x = -CHAR_WIDTH;
-        } else if self.player_state.position.x < -CHAR_WIDTH {
-            self.player_state.position.x = screen_width();
+    /// Enemies touching the player hurt them, unless they were hurt just before.
+    fn take_contact_damage(&mut self, size: Vec2, now: f64) {
+        let state = &mut self.player_state;
+        if state.health == 0 || now < state.invulnerable_until {
+            return;
         }
-        if self.player_state.position.y > screen_height() {
-            self.player_state.position.y = -CHAR_HEIGHT;
-        } else if self.player_state.position.y < -CHAR_HEIGHT {
-            self.player_state.position.y = screen_height();
+        let Some(damage) = self
+            .grid
+            .query(state.position + size / 2., size / 2.)
+            .map(|index| &self.enemies[index])
+            .filter(|enemy| enemy.touches(state.position, size))
+            .filter_map(|enemy| self.settings.enemies.get(enemy.kind))
+            .map(|enemy| enemy.damage)
+            .max()
+        else {
+            return;
+        };
+        state.health = state.health.saturating_sub(damage);
+        state.invulnerable_until = now + f64::from(self.settings.invulnerable_seconds);
+        if state.health == 0 {
+            state.animation.play(AnimationKind::Die, now);
+            self.outgoing.push(ClientMessage::Died);
+        } else {
+            state.animation.play(AnimationKind::Hurt, now);
         }
     }
 
-    #[allow(
-        clippy::cast_precision_loss,
-        clippy::cast_sign_loss,
-        clippy::cast_possible_truncation
-    )]
-    pub fn draw_character(&self, state: &PlayerState) {
-        let cols = (self.texture.width() / CHAR_WIDTH).floor() as usize;
-        let index = state.anim_id % cols;
-        let tx_x = index % cols;
-        let tx_y = index / cols;
+    pub fn draw_character(sheet: &SpriteSheet, position: Vec2, animation: &Animator, color: Color) {
+        let frame = animation.frame(&sheet.animations, get_time());
         draw_texture_ex(
-            self.texture,
-            state.position.x,
-            state.position.y,
-            WHITE,
+            sheet.texture,
+            position.x,
+            position.y,
+            color,
             DrawTextureParams {
-                source: Some(Rect::new(
-                    tx_x as f32 * CHAR_WIDTH,
-                    tx_y as f32 * CHAR_HEIGHT,
-                    CHAR_WIDTH,
-                    CHAR_HEIGHT,
-                )),
+                source: Some(sheet.source(frame)),
                 ..Default::default()
             },
         );
+    }
 
-        egui_macroquad::ui(|egui_ctx| {
-            egui::Window::new("debug").show(egui_ctx, |ui| {
-                ui.label(&format!("Kills: {}", self.player_state.kills));
-            });
+    /// Runs egui once for all panels the scene registered this frame, on top of the world.
+    pub fn draw_ui(&mut self) {
+        let panels = self.ui.take();
+        egui_macroquad::ui(|ctx| {
+            for panel in &panels {
+                match panel {
+                    Panel::Login => self.login_panel(ctx),
+                    Panel::Menu => self.menu_panel(ctx),
+                    Panel::Lobby => self.lobby_panel(ctx),
+                    Panel::Hud => {
+                        hud::player(ctx, self);
+                        hud::opponent(ctx, self);
+                    }
+                    Panel::Debug => hud::debug(ctx, self),
+                    Panel::Results => self.results_panel(ctx),
+                }
+            }
+            self.input
+                .set_ui_focus(ctx.wants_pointer_input(), ctx.wants_keyboard_input());
         });
+        egui_macroquad::draw();
+    }
 
-        // Draw things before egui
+    fn menu_panel(&mut self, ctx: &egui::Context) {
+        egui::Window::new("menu")
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Resume").clicked() {
+                        self.menu_open = false;
+                    }
+                    if ui.button("Quit").clicked() {
+                        self.quit = true;
+                    }
+                });
+                ui.separator();
+                ui.heading("Controls");
+                self.input.rebinding_ui(ui);
+            });
+    }
 
-        egui_macroquad::draw();
+    fn lobby_panel(&mut self, ctx: &egui::Context) {
+        egui::Window::new("lobby")
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::Vec2::new(-8., -8.))
+            .show(ctx, |ui| {
+                ui.label(&format!(
+                    "{} ({})",
+                    self.player_state.name, self.player_state.rating
+                ));
+                ui.separator();
+                let can_challenge = self.opponent.is_none() && self.challenge.is_none();
+                let mut challenged = None;
+                for player in self.players.values() {
+                    ui.horizontal(|ui| {
+                        ui.label(&format!("{} ({})", player.name, player.rating));
+                        if can_challenge && ui.small_button("Challenge").clicked() {
+                            challenged = Some(player.name.clone());
+                        }
+                    });
+                }
+                if let Some(name) = challenged {
+                    self.challenge_player(name);
+                }
+                self.challenges_ui(ui);
+                ui.separator();
+                if let Some(opponent) = self.opponent.and_then(|id| self.players.get(&id)) {
+                    ui.label(&format!("Playing against {}", opponent.name));
+                } else if self.queued {
+                    ui.label(format!(
+                        "Searching for a match... ({} to cancel)",
+                        self.input.describe(Action::Queue)
+                    ));
+                } else {
+                    ui.label(format!(
+                        "Press {} to search for a match",
+                        self.input.describe(Action::Queue)
+                    ));
+                }
+                if self.opponent.is_some() {
+                    ui.separator();
+                    let unspent = self.unspent_kills();
+                    ui.label(format!("Kills to spend: {unspent}"));
+                    let mut send = None;
+                    for (index, enemy) in self.settings.enemies.iter().enumerate() {
+                        let button = egui::Button::new(format!(
+                            "Send {} ({} kills)",
+                            enemy.name, enemy.cost
+                        ));
+                        if ui.add_enabled(unspent >= enemy.cost, button).clicked() {
+                            send = Some(index);
+                        }
+                    }
+                    if let Some(index) = send {
+                        self.send_enemy(index);
+                    }
+                }
+                if let Some(error) = &self.name_error {
+                    let mut dismissed = false;
+                    ui.horizontal(|ui| {
+                        ui.colored_label(egui::Color32::RED, error);
+                        dismissed = ui.small_button("OK").clicked();
+                    });
+                    if dismissed {
+                        self.name_error = None;
+                    }
+                }
+                if let Some(text) = &self.announcement {
+                    ui.colored_label(egui::Color32::YELLOW, text);
+                }
+                if let Some(seconds) = self.shutdown_in {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        format!("Server shutting down in {seconds} seconds"),
+                    );
+                }
+            });
     }
 
-    pub fn draw(&self) {
+    /// Challenges waiting for an answer, from and to this player.
+    fn challenges_ui(&mut self, ui: &mut egui::Ui) {
+        if self.challenges.is_empty() && self.challenge.is_none() {
+            return;
+        }
+        ui.separator();
+        let mut answer = None;
+        for (request_id, name) in &self.challenges {
+            ui.horizontal(|ui| {
+                ui.label(format!("{name} challenges you"));
+                if ui.button("Accept").clicked() {
+                    answer = Some(ClientMessage::AcceptChallenge {
+                        request_id: *request_id,
+                    });
+                }
+                if ui.button("Deny").clicked() {
+                    answer = Some(ClientMessage::DenyChallenge {
+                        request_id: *request_id,
+                    });
+                }
+            });
+        }
+        if let Some(answer) = answer {
+            if let ClientMessage::AcceptChallenge { request_id }
+            | ClientMessage::DenyChallenge { request_id } = answer
+            {
+                self.challenges.remove(&request_id);
+            }
+            self.outgoing.push(answer);
+        }
+        let Some(challenge) = &self.challenge else {
+            return;
+        };
+        if challenge.denied {
+            let mut dismissed = false;
+            ui.horizontal(|ui| {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!("{} didn't accept your challenge", challenge.name),
+                );
+                dismissed = ui.small_button("OK").clicked();
+            });
+            if dismissed {
+                self.challenge = None;
+            }
+        } else {
+            ui.label(format!("Waiting for {} to answer...", challenge.name));
+        }
+    }
+
+    fn results_panel(&mut self, ctx: &egui::Context) {
+        let Some(results) = &mut self.results else {
+            return;
+        };
+        match results::window(ctx, results) {
+            Some(Choice::Rematch) => {
+                results.rematch_requested = true;
+                self.outgoing.push(ClientMessage::Rematch);
+            }
+            Some(Choice::BackToLobby) => {
+                self.results = None;
+                self.outgoing.push(ClientMessage::BackToLobby);
+            }
+            None => {}
+        }
+    }
+
+    fn login_panel(&mut self, ctx: &egui::Context) {
+        let mut submit = false;
+        egui::Window::new("login")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut self.login.name);
+                ui.label("Password");
+                let password =
+                    ui.add(egui::TextEdit::singleline(&mut self.login.password).password(true));
+                if let Some(error) = &self.login.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                if self.disconnected {
+                    self.reconnect = ui.button("Reconnect").clicked();
+                    return;
+                }
+                submit = ui.button("Login").clicked()
+                    || (password.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)));
+            });
+        if submit {
+            self.login.error = None;
+            self.outgoing.push(ClientMessage::Login {
+                name: self.login.name.clone(),
+                password: self.login.password.clone(),
+            });
+        }
+    }
+
+    pub fn draw_login(&mut self) {
         clear_background(color_u8!(0, 211, 205, 205));
-        draw_box(Vec2::new(200f32, 200f32), Vec2::new(10f32, 10f32));
-        self.draw_character(&self.player_state);
+        self.ui.show(Panel::Login);
+    }
+
+    pub fn draw(&mut self) {
+        // everything outside of the arena is letterboxing
+        clear_background(BLACK);
+        set_camera(&WorldCamera::new(self.map.size()).camera);
+        self.map.draw();
+        for pickup in &self.pickups {
+            pickup.draw();
+        }
+        for enemy in &self.enemies {
+            Self::draw_character(
+                self.enemy_sheet(enemy.kind),
+                enemy.position,
+                &enemy.animation,
+                ENEMY_TINT,
+            );
+        }
+        for projectile in &self.projectiles {
+            projectile.draw();
+        }
+        let state = &self.player_state;
+        let now = get_time();
+        // blinking while enemies can't hurt the player
+        #[allow(clippy::cast_possible_truncation)]
+        let color =
+            if state.health > 0 && now < state.invulnerable_until && (now * 10.) as i64 % 2 == 0 {
+                Color::new(1., 1., 1., 0.4)
+            } else {
+                WHITE
+            };
+        Self::draw_character(&self.wizard, state.position, &state.animation, color);
+        let aim = self.player_state.aim;
+        draw_circle_lines(aim.x, aim.y, 3., 1., BLACK);
+        set_default_camera();
+        self.ui.show(Panel::Hud);
+        self.ui.show(Panel::Lobby);
+        if self.show_debug {
+            self.ui.show(Panel::Debug);
+        }
+        if self.results.is_some() {
+            self.ui.show(Panel::Results);
+        }
+        if self.menu_open {
+            self.ui.show(Panel::Menu);
+        }
+    }
+}
+
+/// The cached session is the account name and token, one per line.
+fn load_session() -> Option<(String, String)> {
+    let content = fs::read_to_string(&ARGS.session).ok()?;
+    let mut lines = content.lines();
+    let name = lines.next()?.to_owned();
+    let token = lines.next()?.to_owned();
+    Some((name, token))
+}
+
+fn save_session(name: &str, token: &str) -> io::Result<()> {
+    fs::write(&ARGS.session, format!("{name}\n{token}\n"))
+}
+
+fn clear_session() {
+    if ARGS.session.exists() {
+        if let Err(err) = fs::remove_file(&ARGS.session) {
+            log::warn!("Failed to remove cached session: {}", err);
+        }
     }
 }
 
@@ -257,8 +1205,10 @@ pub fn client_send(msg: &ClientMessage, connection: &Arc<Connection>) {
 
 pub fn client_receive(game: &mut Game, connection: &Arc<Connection>) {
     if let Some(msg) = connection.poll() {
-        let msg: ServerMessage = deserialize(msg.as_slice()).expect("deserialization failed");
-        game.handle_message(msg);
+        match deserialize::<ServerMessage>(msg.as_slice()) {
+            Ok(msg) => game.handle_message(msg),
+            Err(err) => log::error!("Failed to decode message from server: {}", err),
+        }
     }
 }
 
@@ -266,6 +1216,17 @@ pub fn client_receive(game: &mut Game, connection: &Arc<Connection>) {
 struct Arguments {
     #[arg(short, long)]
     address: Option<String>,
+    #[arg(short, long)]
+    name: Option<String>,
+    /// File the login token is cached in
+    #[arg(long, default_value = ".mage_battle_session")]
+    session: PathBuf,
+    /// Directory containing the asset manifest
+    #[arg(long, default_value = "assets")]
+    assets: PathBuf,
+    /// File the key and button bindings are loaded from and saved to
+    #[arg(long, default_value = "controls.toml")]
+    controls: PathBuf,
 }
 
 lazy_static! {
@@ -285,19 +1246,46 @@ async fn main() -> anyhow::Result<()> {
     );
 
     let connection = Arc::new(Connection::new());
-    let connection_coroutine = start_coroutine(client_connect(connection.clone(), address));
+    let mut connection_coroutine =
+        start_coroutine(client_connect(connection.clone(), address.clone()));
 
     let mut game = Game::new().await?;
+    let mut last_state_sent = 0.;
     loop {
         if connection_coroutine.is_done() {
-            let state = ClientMessage::State {
-                kills: game.player_state.kills,
-            };
-            client_send(&state, &connection);
+            // the server only accepts about one state update per tick
+            #[allow(clippy::cast_precision_loss)]
+            let state_interval = 1. / game.settings.tick_rate as f64;
+            if game.disconnected {
+                game.outgoing.clear();
+            } else {
+                if get_time() - last_state_sent >= state_interval {
+                    if let Some(state) = game.state() {
+                        last_state_sent = get_time();
+                        client_send(&state, &connection);
+                    }
+                }
+                for msg in game.outgoing.drain(..) {
+                    client_send(&msg, &connection);
+                }
+            }
             client_receive(&mut game, &connection);
+            if game.reconnect {
+                game.reconnect = false;
+                game.disconnected = false;
+                game.login.error = None;
+                connection.restart();
+                connection_coroutine =
+                    start_coroutine(client_connect(connection.clone(), address.clone()));
+            }
 
-            game.update();
-            game.draw();
+            if game.logged_in {
+                game.update();
+                game.draw();
+            } else {
+                game.draw_login();
+            }
+            game.draw_ui();
         }
         if game.quit {
             return Ok(());
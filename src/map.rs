@@ -0,0 +1,255 @@
+use anyhow::{anyhow, bail};
+use glam::Vec2;
+use macroquad::prelude::{draw_rectangle, Color};
+use serde::Deserialize;
+use shared::MIN_TILE_SIZE;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+struct TileEntry {
+    #[serde(default)]
+    solid: bool,
+    color: [u8; 3],
+}
+
+/// The file format, see `assets/maps`.
+#[derive(Deserialize)]
+struct MapFile {
+    tile_size: f32,
+    spawn: [usize; 2],
+    tiles: String,
+    legend: HashMap<char, TileEntry>,
+}
+
+#[derive(Clone, Copy)]
+struct Tile {
+    solid: bool,
+    color: Color,
+}
+
+/// A grid of tiles, solid ones block movement.
+#[derive(Clone)]
+pub struct TileMap {
+    tile_size: f32,
+    columns: usize,
+    rows: usize,
+    tiles: Vec<Tile>,
+    /// Top left corner of the spawn tile, in world units.
+    pub spawn: Vec2,
+}
+
+impl TileMap {
+    #[allow(clippy::cast_precision_loss)]
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let file: MapFile = toml::from_str(text)?;
+        if file.tile_size < MIN_TILE_SIZE {
+            bail!("tile_size must be at least {}", MIN_TILE_SIZE);
+        }
+        let lines: Vec<&str> = file.tiles.lines().collect();
+        let columns = lines.first().map_or(0, |line| line.chars().count());
+        if columns == 0 {
+            bail!("The map has no tiles");
+        }
+        let mut tiles = Vec::with_capacity(columns * lines.len());
+        for (row, line) in lines.iter().enumerate() {
+            if line.chars().count() != columns {
+                bail!(
+                    "Row {} is {} tiles wide, expected {}",
+                    row + 1,
+                    line.chars().count(),
+                    columns
+                );
+            }
+            for symbol in line.chars() {
+                let entry = file.legend.get(&symbol).ok_or_else(|| {
+                    anyhow!("'{}' in row {} is not in the legend", symbol, row + 1)
+                })?;
+                let [r, g, b] = entry.color;
+                tiles.push(Tile {
+                    solid: entry.solid,
+                    color: Color::from_rgba(r, g, b, 255),
+                });
+            }
+        }
+        let map = Self {
+            tile_size: file.tile_size,
+            columns,
+            rows: lines.len(),
+            tiles,
+            spawn: Vec2::new(file.spawn[0] as f32, file.spawn[1] as f32) * file.tile_size,
+        };
+        let [column, row] = file.spawn;
+        if column >= map.columns || row >= map.rows || map.is_solid(column, row) {
+            bail!("The spawn has to be a tile that isn't solid");
+        }
+        Ok(map)
+    }
+
+    /// Width and height in world units.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.columns as f32, self.rows as f32) * self.tile_size
+    }
+
+    /// Top left corners of the tiles that aren't solid, in world units.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn open_tiles(&self) -> Vec<Vec2> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, tile)| !tile.solid)
+            .map(|(index, _)| {
+                let column = (index % self.columns) as f32;
+                let row = (index / self.columns) as f32;
+                Vec2::new(column, row) * self.tile_size
+            })
+            .collect()
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The column and row of the tile containing the point, `None` outside of the map.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn tile_at(&self, point: Vec2) -> Option<(usize, usize)> {
+        if point.x < 0. || point.y < 0. {
+            return None;
+        }
+        let column = (point.x / self.tile_size) as usize;
+        let row = (point.y / self.tile_size) as usize;
+        (column < self.columns && row < self.rows).then_some((column, row))
+    }
+
+    /// The center of the tile, in world units.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn tile_center(&self, (column, row): (usize, usize)) -> Vec2 {
+        (Vec2::new(column as f32, row as f32) + Vec2::splat(0.5)) * self.tile_size
+    }
+
+    /// Everything outside of the map is open.
+    pub fn is_solid(&self, column: usize, row: usize) -> bool {
+        column < self.columns && row < self.rows && self.tiles[row * self.columns + column].solid
+    }
+
+    /// The columns or rows covered by the range, in world units, excluding its far edge.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn cells(&self, start: f32, length: f32) -> std::ops::RangeInclusive<usize> {
+        let first = (start / self.tile_size).floor().max(0.) as usize;
+        let last = ((start + length) / self.tile_size).ceil().max(1.) as usize - 1;
+        first..=last
+    }
+
+    /// Whether the box, with `position` being its top left corner, touches a solid tile.
+    pub fn overlaps_solid(&self, position: Vec2, size: Vec2) -> bool {
+        if position.x + size.x <= 0. || position.y + size.y <= 0. {
+            return false;
+        }
+        self.cells(position.y, size.y).any(|row| {
+            self.cells(position.x, size.x)
+                .any(|column| self.is_solid(column, row))
+        })
+    }
+
+    /// Moves the box by `delta`, one axis at a time, stopping it at the first solid tile in the way.
+    /// Moves have to be smaller than a tile, so nothing can skip through a wall,
+    /// the server only accepts speeds that stay below [`MIN_TILE_SIZE`] per tick.
+    pub fn move_and_collide(&self, position: Vec2, size: Vec2, delta: Vec2) -> Vec2 {
+        let mut moved = position;
+        moved.x += delta.x;
+        if delta.x != 0. && self.overlaps_solid(moved, size) {
+            moved.x = if delta.x > 0. {
+                ((moved.x + size.x) / self.tile_size).floor() * self.tile_size - size.x
+            } else {
+                (moved.x / self.tile_size).floor() * self.tile_size + self.tile_size
+            };
+        }
+        moved.y += delta.y;
+        if delta.y != 0. && self.overlaps_solid(moved, size) {
+            moved.y = if delta.y > 0. {
+                ((moved.y + size.y) / self.tile_size).floor() * self.tile_size - size.y
+            } else {
+                (moved.y / self.tile_size).floor() * self.tile_size + self.tile_size
+            };
+        }
+        moved
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn draw(&self) {
+        for (index, tile) in self.tiles.iter().enumerate() {
+            let column = (index % self.columns) as f32;
+            let row = (index / self.columns) as f32;
+            draw_rectangle(
+                column * self.tile_size,
+                row * self.tile_size,
+                self.tile_size,
+                self.tile_size,
+                tile.color,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = r##"
+tile_size = 16
+spawn = [1, 1]
+tiles = """
+####
+#..#
+#.##
+####"""
+
+[legend]
+"#" = { solid = true, color = [0, 0, 0] }
+"." = { color = [255, 255, 255] }
+"##;
+
+    #[test]
+    fn parses_tiles_and_spawn() {
+        let map = TileMap::parse(MAP).unwrap();
+        assert_eq!((map.columns(), map.rows()), (4, 4));
+        assert_eq!(map.size(), Vec2::splat(64.));
+        assert_eq!(map.spawn, Vec2::splat(16.));
+        assert!(map.is_solid(0, 0));
+        assert!(!map.is_solid(2, 1));
+        assert_eq!(map.open_tiles().len(), 3);
+        assert_eq!(map.tile_at(Vec2::new(40., 20.)), Some((2, 1)));
+        assert_eq!(map.tile_at(Vec2::new(-1., 20.)), None);
+    }
+
+    #[test]
+    fn rejects_broken_maps() {
+        assert!(TileMap::parse(&MAP.replace("#..#", "#..")).is_err());
+        assert!(TileMap::parse(&MAP.replace("#..#", "#.?#")).is_err());
+        assert!(TileMap::parse(&MAP.replace("[1, 1]", "[0, 0]")).is_err());
+        assert!(TileMap::parse(&MAP.replace("tile_size = 16", "tile_size = 8")).is_err());
+    }
+
+    #[test]
+    fn walls_stop_moves() {
+        let map = TileMap::parse(MAP).unwrap();
+        let size = Vec2::splat(8.);
+        let start = Vec2::new(20., 20.);
+        // right into the wall at column 3
+        let moved = map.move_and_collide(Vec2::new(28., 20.), size, Vec2::new(15., 0.));
+        assert_eq!(moved, Vec2::new(40., 20.));
+        // down into the wall at row 2
+        let moved = map.move_and_collide(Vec2::new(36., 20.), size, Vec2::new(0., 10.));
+        assert_eq!(moved, Vec2::new(36., 24.));
+        // left stops at column 0, down still goes on into the open tile below
+        let moved = map.move_and_collide(Vec2::new(28., 20.), size, Vec2::new(-15., 10.));
+        assert_eq!(moved, Vec2::new(16., 30.));
+        // open space doesn't get in the way
+        let moved = map.move_and_collide(start, size, Vec2::new(2., 3.));
+        assert_eq!(moved, Vec2::new(22., 23.));
+    }
+}
@@ -0,0 +1,39 @@
+use glam::Vec2;
+use macroquad::prelude::{draw_rectangle, draw_rectangle_lines, Color, BLACK};
+use shared::PickupKind;
+
+/// In world units.
+const SIZE: f32 = 8.;
+
+/// Something a killed enemy dropped, lying around until the player walks over it.
+pub struct Pickup {
+    pub kind: PickupKind,
+    /// Where its center is.
+    pub position: Vec2,
+}
+
+impl Pickup {
+    /// Whether the box, `position` being its top left corner, overlaps the pickup.
+    pub fn touches(&self, position: Vec2, size: Vec2) -> bool {
+        let corner = self.position - Vec2::splat(SIZE / 2.);
+        corner.x < position.x + size.x
+            && position.x < corner.x + SIZE
+            && corner.y < position.y + size.y
+            && position.y < corner.y + SIZE
+    }
+
+    pub fn draw(&self) {
+        let corner = self.position - Vec2::splat(SIZE / 2.);
+        draw_rectangle(corner.x, corner.y, SIZE, SIZE, color(self.kind));
+        draw_rectangle_lines(corner.x, corner.y, SIZE, SIZE, 1., BLACK);
+    }
+}
+
+pub fn color(kind: PickupKind) -> Color {
+    match kind {
+        PickupKind::Health => Color::new(0.9, 0.2, 0.2, 1.),
+        PickupKind::Mana => Color::new(0.2, 0.4, 0.9, 1.),
+        PickupKind::Damage => Color::new(0.9, 0.6, 0.1, 1.),
+        PickupKind::Speed => Color::new(0.2, 0.8, 0.3, 1.),
+    }
+}
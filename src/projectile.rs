@@ -0,0 +1,70 @@
+use crate::{enemy::Enemy, map::TileMap};
+use glam::Vec2;
+use macroquad::prelude::{draw_circle, Color};
+
+/// In world units.
+pub const RADIUS: f32 = 2.;
+
+/// Bolts that hit nothing vanish after this many seconds.
+const LIFETIME_SECONDS: f32 = 1.5;
+
+/// How much health a bolt takes from the enemy it hits.
+pub const DAMAGE: u32 = 1;
+
+/// How much mana casting a bolt costs.
+pub const MANA_COST: f32 = 10.;
+
+/// Seconds between two bolts.
+pub const COOLDOWN_SECONDS: f64 = 0.4;
+
+const COLOR: Color = Color::new(0.6, 0.8, 1., 1.);
+
+/// A bolt the player cast, flying straight until it hits an enemy or a wall.
+pub struct Projectile {
+    pub position: Vec2,
+    velocity: Vec2,
+    damage: u32,
+    ticks_left: u32,
+}
+
+impl Projectile {
+    /// Flies from `from` towards `target` at `speed`, `None` if they are the same point.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn new(from: Vec2, target: Vec2, speed: f32, damage: u32, tick: f32) -> Option<Self> {
+        let direction = (target - from).try_normalize()?;
+        Some(Self {
+            position: from,
+            velocity: direction * speed,
+            damage,
+            ticks_left: (LIFETIME_SECONDS / tick) as u32,
+        })
+    }
+
+    /// Moves the bolt by one tick, `false` once it hit a wall, left the map or ran out of time.
+    pub fn step(&mut self, map: &TileMap, tick: f32) -> bool {
+        self.position += self.velocity * tick;
+        self.ticks_left = self.ticks_left.saturating_sub(1);
+        let in_wall = map
+            .tile_at(self.position)
+            .is_none_or(|(column, row)| map.is_solid(column, row));
+        self.ticks_left > 0 && !in_wall
+    }
+
+    pub fn hits(&self, enemy: &Enemy) -> bool {
+        enemy.touches(
+            self.position - Vec2::splat(RADIUS),
+            Vec2::splat(RADIUS * 2.),
+        )
+    }
+
+    /// Hurts the enemy, returns how much health it took.
+    pub fn strike(&self, enemy: &mut Enemy) -> u32 {
+        let damage = self.damage.min(enemy.health);
+        enemy.health -= damage;
+        damage
+    }
+
+    pub fn draw(&self) {
+        draw_circle(self.position.x, self.position.y, RADIUS, COLOR);
+    }
+}
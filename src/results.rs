@@ -0,0 +1,115 @@
+use crate::hud::clock;
+use egui::{Align2, Color32, Context, Grid};
+use macroquad::prelude::get_time;
+
+/// How the last match went, shown until the player picks what's next.
+pub struct MatchResults {
+    /// `None` if the match was aborted.
+    pub won: Option<bool>,
+    pub opponent: String,
+    pub kills: usize,
+    pub enemy_kills: usize,
+    pub enemies_sent: usize,
+    pub enemies_received: usize,
+    /// Health taken from enemies with bolts.
+    pub damage_dealt: u32,
+    pub seconds: u64,
+    pub rating_change: i64,
+    /// A rematch is possible until this time, if the opponent doesn't leave.
+    pub rematch_until: f64,
+    /// The player asked for a rematch and waits for the opponent.
+    pub rematch_requested: bool,
+    pub opponent_wants_rematch: bool,
+    pub opponent_left: bool,
+}
+
+impl MatchResults {
+    /// What the rematch button says and whether it can be pressed.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn rematch_button(&self) -> (String, bool) {
+        let seconds_left = (self.rematch_until - get_time()).ceil();
+        if self.opponent_left {
+            (format!("{} went back to the lobby", self.opponent), false)
+        } else if seconds_left <= 0. {
+            ("No rematch".to_owned(), false)
+        } else if self.rematch_requested {
+            (
+                format!("Waiting for {}... {}s", self.opponent, seconds_left as u64),
+                false,
+            )
+        } else if self.opponent_wants_rematch {
+            (format!("Accept rematch ({}s)", seconds_left as u64), true)
+        } else {
+            (format!("Rematch ({}s)", seconds_left as u64), true)
+        }
+    }
+}
+
+pub enum Choice {
+    Rematch,
+    BackToLobby,
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub fn window(ctx: &Context, results: &MatchResults) -> Option<Choice> {
+    let mut choice = None;
+    egui::Window::new("results")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            let (title, color) = match results.won {
+                Some(true) => ("Victory", Color32::GREEN),
+                Some(false) => ("Defeat", Color32::RED),
+                None => ("Match aborted", Color32::YELLOW),
+            };
+            ui.vertical_centered(|ui| {
+                ui.heading(egui::RichText::new(title).color(color));
+                ui.label(format!("against {}", results.opponent));
+            });
+            ui.separator();
+            Grid::new("results_grid")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("");
+                    ui.strong("You");
+                    ui.strong("Opponent");
+                    ui.end_row();
+                    ui.label("Kills");
+                    ui.label(results.kills.to_string());
+                    ui.label(results.enemy_kills.to_string());
+                    ui.end_row();
+                    ui.label("Enemies sent");
+                    ui.label(results.enemies_sent.to_string());
+                    ui.label(results.enemies_received.to_string());
+                    ui.end_row();
+                    ui.label("Damage dealt");
+                    ui.label(results.damage_dealt.to_string());
+                    ui.label("");
+                    ui.end_row();
+                });
+            ui.separator();
+            ui.label(format!("Duration {}", clock(results.seconds as f64)));
+            let change = match results.rating_change {
+                0 => egui::RichText::new("Rating unchanged"),
+                change if change > 0 => {
+                    egui::RichText::new(format!("Rating +{change}")).color(Color32::GREEN)
+                }
+                change => egui::RichText::new(format!("Rating {change}")).color(Color32::RED),
+            };
+            ui.label(change);
+            ui.separator();
+            ui.horizontal(|ui| {
+                let (text, enabled) = results.rematch_button();
+                if ui.add_enabled(enabled, egui::Button::new(text)).clicked() {
+                    choice = Some(Choice::Rematch);
+                }
+                if ui.button("Back to Lobby").clicked() {
+                    choice = Some(Choice::BackToLobby);
+                }
+            });
+        });
+    choice
+}
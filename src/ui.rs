@@ -0,0 +1,33 @@
+/// A part of the interface. Scenes register the ones they show every frame and
+/// [`crate::Game::draw_ui`] runs egui once for all of them, after the world is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Panel {
+    Login,
+    Menu,
+    Lobby,
+    /// Health, mana, spells, kills, the opponent and the match timer.
+    Hud,
+    /// Frame rate, ping and entity counts.
+    Debug,
+    /// How the last match went.
+    Results,
+}
+
+#[derive(Default)]
+pub struct UiLayer {
+    panels: Vec<Panel>,
+}
+
+impl UiLayer {
+    /// Shows the panel this frame, later panels are drawn on top of earlier ones.
+    pub fn show(&mut self, panel: Panel) {
+        if !self.panels.contains(&panel) {
+            self.panels.push(panel);
+        }
+    }
+
+    /// The panels registered this frame, leaving none for the next one.
+    pub fn take(&mut self) -> Vec<Panel> {
+        std::mem::take(&mut self.panels)
+    }
+}